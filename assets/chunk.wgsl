@@ -3,71 +3,147 @@
     pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
 }
 
-#import bevy_pbr::mesh_functions::{get_world_from_local, mesh_position_local_to_clip, mesh_normal_local_to_world}
 #import bevy_pbr::pbr_functions::{calculate_view, prepare_world_normal}
-#import bevy_pbr::mesh_bindings::mesh
+#import bevy_pbr::mesh_types::MESH_FLAGS_SHADOW_RECEIVER_BIT
 #import bevy_pbr::pbr_types::pbr_input_new
 #import bevy_pbr::prepass_utils
 #import bevy_pbr::mesh_view_bindings::view
 #import bevy_core_pipeline::tonemapping::tone_mapping
 
-@group(2) @binding(0) var textures: binding_array<texture_2d_array<f32>>;
-@group(2) @binding(1) var nearest_sampler: sampler;
+@group(1) @binding(0) var textures: texture_2d_array<f32>;
+@group(1) @binding(1) var nearest_sampler: sampler;
 
-struct Vertex {
-    @builtin(instance_index) instance_index: u32,
-    @location(0) data: u32
+// Section pass buffer: faces are one quad per item, models are 4 vertices per quad
+struct Section {
+    origin: vec3<f32>,
+    faces: u32,
+    data: array<vec2<u32>>,
 };
 
+@group(2) @binding(0) var<storage, read> section: Section;
+
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) world_normal: vec3<f32>,
     @location(1) world_position: vec4<f32>,
     @location(2) uv: vec2<f32>,
     @location(3) b: u32,
-    @location(4) side: u32,
-    @location(5) instance_index: u32,
+    @location(4) ao: f32,
+    @location(5) light: f32,
 };
 
-var<private> normals: array<vec3<f32>, 6> = array<vec3<f32>,6> (
+var<private> normals: array<vec3<f32>, 8> = array<vec3<f32>,8> (
 	vec3<f32>(0.0, 1.0, 0.0),   // Up
     vec3<f32>(-1.0, 0.0, 0.0),  // Left
 	vec3<f32>(1.0, 0.0, 0.0),   // Right
 	vec3<f32>(0.0, 0.0, -1.0),  // Forward
 	vec3<f32>(0.0, 0.0, 1.0),   // Back
     vec3<f32>(0.0, -1.0, 0.0),  // Down
+    vec3<f32>(0.0, 1.0, 0.0),   // Crossed front (lit from above)
+    vec3<f32>(0.0, 1.0, 0.0),   // Crossed back
 );
 
+// Faces with counter-clockwise corners in reverse order (Direction::reverse_order)
+var<private> reverse_order: array<bool, 6> = array<bool, 6>(true, false, true, true, false, false);
+
 fn x_bits(bits: u32) -> u32{
     return (1u << bits) - 1u;
 }
 
-@vertex
-fn vertex(vertex: Vertex) -> VertexOutput {
-    var out: VertexOutput;
+// Block position from face slice grid (Direction::world_sample)
+fn world_sample(side: u32, axis: f32, row: f32, column: f32) -> vec3<f32> {
+    if side == 0u || side == 5u {
+        return vec3<f32>(row, axis, column);
+    } else if side == 1u || side == 2u {
+        return vec3<f32>(axis, column, row);
+    }
+    return vec3<f32>(row, column, axis);
+}
 
-    let x = f32(vertex.data & x_bits(6u));
-    let y = f32(vertex.data >> 6u & x_bits(6u));
-    let z = f32(vertex.data >> 12u & x_bits(6u));
-    let normal_index = vertex.data >> 18u & x_bits(3u);
-    let b = vertex.data >> 21u & x_bits(7u);
-    let uvx = vertex.data >> 28u & x_bits(1u);
-    let uvy = vertex.data >> 29u & x_bits(1u);
-
-    let local_position = vec4<f32>(x, y, z, 1.0);
-    let world_position = get_world_from_local(vertex.instance_index) * local_position;
-    out.clip_position = mesh_position_local_to_clip(
-        get_world_from_local(vertex.instance_index),
-        local_position,
-    );
+// Texture coords of face point, repeat over merged faces (Vertex::face_uv)
+fn face_uv(local: vec3<f32>, side: u32) -> vec2<f32> {
+    var uv = local.xy;
+    if side == 0u || side == 5u {
+        uv = local.xz;
+    } else if side == 1u || side == 2u {
+        uv = local.zy;
+    }
+    return vec2<f32>(32.0) - uv;
+}
 
+@vertex
+fn vertex(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    var local: vec3<f32>;
+    var side: u32;
+    var light: u32;
+    var ao = 3u;
+
+    let quad = vertex_index / 4u;
+    if quad < section.faces {
+        // Greedy face: expand corner from quad
+        let data = section.data[quad];
+        let x = f32(data.x & x_bits(5u));
+        let y = f32(data.x >> 5u & x_bits(5u));
+        let w = f32((data.x >> 10u & x_bits(5u)) + 1u);
+        let h = f32((data.x >> 15u & x_bits(5u)) + 1u);
+        let axis = f32(data.x >> 20u & x_bits(6u));
+        side = data.x >> 26u & x_bits(3u);
+
+        let aos = vec4<u32>(data.y >> 16u, data.y >> 18u, data.y >> 20u, data.y >> 22u) & vec4<u32>(x_bits(2u));
+        light = data.y >> 24u & x_bits(4u);
+
+        // Split quad along other diagonal, so AO gradient isn't anisotropic
+        var k = vertex_index % 4u;
+        if aos.x + aos.z < aos.y + aos.w {
+            k = (k + 1u) % 4u;
+        }
+        if reverse_order[side] {
+            k = (4u - k) % 4u;
+        }
+
+        let corner = vec2<f32>(select(0.0, w, k == 1u || k == 2u), select(0.0, h, k >= 2u));
+        local = world_sample(side, axis, x + corner.x, y + corner.y);
+        ao = aos[k];
+        out.uv = face_uv(local, side);
+        out.b = data.y & x_bits(16u);
+    } else {
+        // Models vertex, stored after faces
+        let data = section.data[vertex_index - 3u * section.faces];
+        let is_precise = (data.x >> 25u & 1u) == 1u;
+        let detail = ((data.x >> 26u) << 16u) | (data.y >> 16u);
+        side = data.x >> 18u & x_bits(3u);
+        light = data.x >> 21u & x_bits(4u);
+
+        var fraction = vec3<f32>(0.0);
+        if is_precise {
+            // Custom models fraction position and UV in 1/16
+            fraction = vec3<f32>(
+                f32(detail & x_bits(4u)),
+                f32(detail >> 4u & x_bits(4u)),
+                f32(detail >> 8u & x_bits(4u)),
+            ) / 16.0;
+            out.uv = vec2<f32>(f32(detail >> 12u & x_bits(5u)), f32(detail >> 17u & x_bits(5u))) / 16.0;
+        } else {
+            // Crossed models UV in blocks
+            ao = detail & x_bits(2u);
+            out.uv = vec2<f32>(f32(detail >> 2u & x_bits(6u)), f32(detail >> 8u & x_bits(6u)));
+        }
+
+        local = vec3<f32>(
+            f32(data.x & x_bits(6u)),
+            f32(data.x >> 6u & x_bits(6u)),
+            f32(data.x >> 12u & x_bits(6u)),
+        ) + fraction;
+        out.b = data.y & x_bits(16u);
+    }
+
+    let world_position = vec4<f32>(section.origin + local, 1.0);
+    out.clip_position = view.clip_from_world * world_position;
     out.world_position = world_position;
-    let normal = normals[normal_index];
-    out.world_normal = mesh_normal_local_to_world(normal, vertex.instance_index);
-    out.uv = vec2(f32(uvx), f32(uvy));
-    out.b = b;
-    out.side = normal_index;
-    out.instance_index = vertex.instance_index;
+    out.world_normal = normals[side];
+    out.ao = f32(ao) / 3.0;
+    out.light = f32(light) / 15.0;
     return out;
 }
 
@@ -75,8 +151,17 @@ fn vertex(vertex: Vertex) -> VertexOutput {
 fn fragment(input: VertexOutput) -> FragmentOutput {
     var pbr_input = pbr_input_new();
 
-    pbr_input.flags = mesh[input.instance_index].flags;
-    pbr_input.material.base_color = textureSample(textures[input.b], nearest_sampler, input.uv, input.side);
+    pbr_input.flags = MESH_FLAGS_SHADOW_RECEIVER_BIT;
+    pbr_input.material.base_color = textureSample(textures, nearest_sampler, input.uv, input.b);
+#ifdef CUTOUT
+    // Alpha cutout (leaves, crossed and custom models)
+    if pbr_input.material.base_color.a < 0.5 {
+        discard;
+    }
+#endif
+    // Darken occluded corners and unlit blocks
+    let shade = mix(0.35, 1.0, input.ao) * mix(0.05, 1.0, input.light);
+    pbr_input.material.base_color = vec4(pbr_input.material.base_color.rgb * shade, pbr_input.material.base_color.a);
 
     pbr_input.V = calculate_view(input.world_position, pbr_input.is_orthographic);
     pbr_input.frag_coord = input.clip_position;
@@ -98,4 +183,4 @@ fn fragment(input: VertexOutput) -> FragmentOutput {
 
     out.color = tone_mapping(apply_pbr_lighting(pbr_input), view.color_grading);
     return out;
-}
\ No newline at end of file
+}
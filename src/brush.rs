@@ -0,0 +1,220 @@
+//! Terrain sculpting brushes
+
+use bevy::{
+    prelude::*,
+    input::mouse::MouseWheel,
+};
+use bevy_egui::{egui, EguiContexts};
+use super::*;
+
+pub const MAX_RADIUS: i32 = 16;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BrushShape {
+    #[default]
+    Sphere,
+    Cylinder,
+    Cube
+}
+
+impl BrushShape {
+    /// Is offset from brush center inside shape
+    pub fn contains(&self, offset: IVec3, radius: i32) -> bool {
+        let r = (radius as f32 + 0.5).powi(2);
+        match self {
+            Self::Sphere => offset.as_vec3().length_squared() <= r,
+            Self::Cylinder => offset.xz().as_vec2().length_squared() <= r && offset.y.abs() <= radius,
+            Self::Cube => offset.abs().max_element() <= radius
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BrushMode {
+    /// Fill empty blocks
+    #[default]
+    Place,
+    Erase,
+    /// Remove lonely blocks and fill holes
+    Smooth,
+    /// Replace only blocks exposed to air
+    Surface
+}
+
+#[derive(Resource)]
+pub struct Brush {
+    pub enabled: bool,
+    pub shape: BrushShape,
+    pub mode: BrushMode,
+    pub radius: i32
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            shape: BrushShape::default(),
+            mode: BrushMode::default(),
+            radius: 3
+        }
+    }
+}
+
+/// Blocks copy around brush center (radius + 1 for neighbours)
+struct Snapshot {
+    min: IVec3,
+    size: i32,
+    data: Vec<u16>
+}
+
+impl Snapshot {
+    fn new(controller: &Controller, center: IVec3, radius: i32) -> Self {
+        let r = radius + 1;
+        let size = r * 2 + 1;
+        let min = center - IVec3::splat(r);
+
+        let mut data = Vec::with_capacity((size * size * size) as usize);
+        for y in 0..size {
+            for z in 0..size {
+                for x in 0..size {
+                    data.push(controller.get_block(min + IVec3::new(x, y, z)).unwrap_or(0));
+                }
+            }
+        }
+
+        Self { min, size, data }
+    }
+
+    fn get(&self, pos: IVec3) -> u16 {
+        let p = pos - self.min;
+        if p.cmplt(IVec3::ZERO).any() || p.cmpge(IVec3::splat(self.size)).any() {
+            return 0;
+        }
+        self.data[(p.x + p.z * self.size + p.y * self.size * self.size) as usize]
+    }
+
+    /// Is any of 6 neighbours empty
+    fn is_exposed(&self, pos: IVec3) -> bool {
+        [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z]
+            .iter().any(|o| self.get(pos + *o) == 0)
+    }
+
+    /// Solid blocks in 3x3x3 area and most frequent of them
+    fn neighbours(&self, pos: IVec3) -> (usize, u16) {
+        let mut counts: Vec<(u16, usize)> = Vec::with_capacity(27);
+        let mut solid = 0;
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let b = self.get(pos + IVec3::new(x, y, z));
+                    if b == 0 { continue; }
+
+                    solid += 1;
+                    match counts.iter_mut().find(|(k, _)| *k == b) {
+                        Some((_, n)) => *n += 1,
+                        None => counts.push((b, 1))
+                    }
+                }
+            }
+        }
+
+        let common = counts.into_iter().max_by_key(|(_, n)| *n).map(|(b, _)| b).unwrap_or(0);
+        (solid, common)
+    }
+}
+
+impl Brush {
+    /// All brush block changes around center, block is used by place and surface modes
+    pub fn changes(&self, controller: &Controller, center: IVec3, block: u16) -> Vec<(IVec3, u16)> {
+        let snapshot = Snapshot::new(controller, center, self.radius);
+        let r = self.radius;
+
+        let mut result = Vec::new();
+        for x in -r..=r {
+            for y in -r..=r {
+                for z in -r..=r {
+                    let offset = IVec3::new(x, y, z);
+                    if !self.shape.contains(offset, r) { continue; }
+
+                    let pos = center + offset;
+                    let current = snapshot.get(pos);
+                    let new = match self.mode {
+                        BrushMode::Place => if current == 0 { block } else { current },
+                        BrushMode::Erase => 0,
+                        BrushMode::Surface => {
+                            if current != 0 && snapshot.is_exposed(pos) { block } else { current }
+                        },
+                        BrushMode::Smooth => {
+                            let (solid, common) = snapshot.neighbours(pos);
+                            if solid > 13 && current == 0 { common }
+                            else if solid < 13 { 0 }
+                            else { current }
+                        }
+                    };
+
+                    if new != current {
+                        result.push((pos, new));
+                    }
+                }
+            }
+        }
+
+        result
+    }
+}
+
+/// Brush radius by scroll and brush applying
+pub fn update(
+    mut brush: ResMut<Brush>,
+    mut controller: ResMut<Controller>,
+    mut scroll: EventReader<MouseWheel>,
+    kbd: Res<ButtonInput<KeyCode>>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    selected: Res<systems::SelectedData>,
+    hotbar: Res<hotbar::Hotbar>
+) {
+    if kbd.just_pressed(KeyCode::KeyB) {
+        brush.enabled = !brush.enabled;
+    }
+
+    let delta: f32 = scroll.read().map(|e| e.y).sum();
+    if !brush.enabled { return; }
+
+    if kbd.pressed(SCROLL_MODIFIER) && delta != 0.0 {
+        brush.radius = (brush.radius + delta.signum() as i32).clamp(0, MAX_RADIUS);
+    }
+
+    let Some(hit) = selected.current else { return };
+    if mouse_buttons.just_pressed(MouseButton::Left) {
+        let center = match brush.mode {
+            BrushMode::Place => hit.adjacent(),
+            _ => hit.block
+        };
+
+        let changes = brush.changes(&controller, center, hotbar.selected());
+        controller.set_blocks(changes);
+    }
+}
+
+/// Brush settings window
+pub fn ui(
+    mut contexts: EguiContexts,
+    mut brush: ResMut<Brush>
+) {
+    egui::Window::new("Brush").default_open(false).show(contexts.ctx_mut(), |ui| {
+        ui.checkbox(&mut brush.enabled, "Enabled (B)");
+
+        ui.horizontal(|ui| {
+            for (shape, name) in [(BrushShape::Sphere, "Sphere"), (BrushShape::Cylinder, "Cylinder"), (BrushShape::Cube, "Cube")] {
+                ui.selectable_value(&mut brush.shape, shape, name);
+            }
+        });
+        ui.horizontal(|ui| {
+            for (mode, name) in [(BrushMode::Place, "Place"), (BrushMode::Erase, "Erase"), (BrushMode::Smooth, "Smooth"), (BrushMode::Surface, "Surface")] {
+                ui.selectable_value(&mut brush.mode, mode, name);
+            }
+        });
+
+        ui.add(egui::Slider::new(&mut brush.radius, 0..=MAX_RADIUS).text("Radius (Alt + Scroll)"));
+    });
+}
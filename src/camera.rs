@@ -6,6 +6,9 @@ use bevy::{
 use bevy::window::*;
 use std::f32::consts::PI;
 
+/// Scroll is used by tools (brush radius, hotbar) while held
+pub const SCROLL_MODIFIER: KeyCode = KeyCode::AltLeft;
+
 pub struct CameraController {
     pub speed: f32,
     pub sensitivity: f32,
@@ -62,6 +65,9 @@ fn camera_control(
         scroll += event.y
     }
 
+    // Scroll is handled by tools with modifier
+    if kbd.pressed(SCROLL_MODIFIER) { scroll = 0.0; }
+
     if let Ok((mut camera, mut transform)) = cameras.get_single_mut() {
         let forward = transform.forward().normalize();
         let mut speed = camera.controller.speed;
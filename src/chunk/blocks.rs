@@ -4,6 +4,8 @@ use bevy::{
 };
 use serde::{Serialize, Deserialize};
 use ordermap::OrderMap;
+use super::{BakedModel, CustomModel};
+use crate::mesher::Direction;
 
 /// Contains block/model collision box
 #[derive(Clone, Serialize, Deserialize)]
@@ -25,13 +27,65 @@ impl Default for CollisionBox {
     }
 }
 
+/// Per-face texture paths, the most specific one is used:
+/// north/south/east/west over side, side/top/bottom over all
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FacePaths {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub all: Option<AssetPath<'static>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top: Option<AssetPath<'static>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bottom: Option<AssetPath<'static>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub side: Option<AssetPath<'static>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub north: Option<AssetPath<'static>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub south: Option<AssetPath<'static>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub east: Option<AssetPath<'static>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub west: Option<AssetPath<'static>>,
+}
+
+/// Meshable block textures: one for all faces or per-face paths
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FaceTextures {
+    Single(AssetPath<'static>),
+    Faces(Box<FacePaths>)
+}
+
+impl FaceTextures {
+    /// Texture path of face
+    pub fn get(&self, dir: Direction) -> Option<&AssetPath<'static>> {
+        let faces = match self {
+            Self::Single(path) => return Some(path),
+            Self::Faces(faces) => faces
+        };
+
+        let (specific, side) = match dir {
+            Direction::Up => (&faces.top, None),
+            Direction::Down => (&faces.bottom, None),
+            Direction::Forward => (&faces.north, faces.side.as_ref()),
+            Direction::Back => (&faces.south, faces.side.as_ref()),
+            Direction::Right => (&faces.east, faces.side.as_ref()),
+            Direction::Left => (&faces.west, faces.side.as_ref()),
+        };
+        specific.as_ref().or(side).or(faces.all.as_ref())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 // todo models
 pub enum ModelType {
     /// Empty model
     Empty,
     /// Standart block type
-    Meshable(AssetPath<'static>),
+    Meshable(FaceTextures),
+    /// Two diagonal alpha-cutout quads (plants, flowers), first texture layer is used
     Crossed(AssetPath<'static>),
     Custom(AssetPath<'static>)
 }
@@ -45,10 +99,26 @@ impl ModelType {
     }
 }
 
+/// How block faces are blended
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Opacity {
+    #[default]
+    Opaque,
+    /// Fully opaque or fully transparent pixels (leaves, grates), drawn in separate mesh
+    Cutout,
+    /// Blended faces (glass, water, ice), drawn in separate mesh
+    Translucent
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct BlockType {
     model: ModelType,
     collision: Option<CollisionBox>,
+    #[serde(default)]
+    opacity: Opacity,
+    /// Does block hide neighbours faces, by default only opaque meshable blocks do
+    #[serde(default)]
+    occludes: Option<bool>,
 }
 
 impl BlockType {
@@ -57,8 +127,15 @@ impl BlockType {
         Self {
             collision: model.collision(),
             model,
+            opacity: Opacity::default(),
+            occludes: None,
         }
     }
+
+    /// Occlusion flag or default one from model and opacity
+    pub fn occludes(&self) -> bool {
+        self.occludes.unwrap_or(self.opacity == Opacity::Opaque && matches!(self.model, ModelType::Meshable(_)))
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -69,23 +146,24 @@ impl Default for Blocks {
     fn default() -> Self {
         Self(OrderMap::from([
             ("Air".into(), BlockType::new(ModelType::Empty)),
-            ("Dirt".into(), BlockType::new(ModelType::Meshable("dirt.png".into()))),
-            ("Grass".into(), BlockType::new(ModelType::Meshable("grass.png".into()))),
-            ("Stone".into(), BlockType::new(ModelType::Meshable("stone.png".into()))),
+            ("Dirt".into(), BlockType::new(ModelType::Meshable(FaceTextures::Single("dirt.png".into())))),
+            ("Grass".into(), BlockType::new(ModelType::Meshable(FaceTextures::Faces(Box::new(FacePaths {
+                top: Some("grass_top.png".into()),
+                bottom: Some("grass_bottom.png".into()),
+                side: Some("grass_side.png".into()),
+                ..default()
+            }))))),
+            ("Stone".into(), BlockType::new(ModelType::Meshable(FaceTextures::Single("stone.png".into())))),
         ]))
     }
 }
 
-/// todo: Custom model load logic, rendering etc
-#[derive(Clone, Asset, TypePath)]
-pub struct CustomModel {
-    //todo
-}
-
 /// Contains all models textures & data
+#[derive(Clone)]
 pub enum Model {
     Empty,
-    Meshable(Handle<Image>),
+    /// Textures in Direction::to_u32 order
+    Meshable([Handle<Image>; 6]),
     Crossed(Handle<Image>),
     Custom(Handle<CustomModel>)
 }
@@ -94,7 +172,16 @@ impl Model {
     pub fn load(assets: &AssetServer, t: ModelType) -> Self {
         match t {
             ModelType::Empty => Self::Empty,
-            ModelType::Meshable(path) => Self::Meshable(assets.load(path)),
+            ModelType::Meshable(textures) => Self::Meshable([
+                Direction::Up, Direction::Left, Direction::Right,
+                Direction::Forward, Direction::Back, Direction::Down
+            ].map(|dir| match textures.get(dir) {
+                Some(path) => assets.load(path.clone()),
+                None => {
+                    println!("No texture for {:?} face", dir);
+                    Handle::default()
+                }
+            })),
             ModelType::Crossed(path) => Self::Crossed(assets.load(path)),
             ModelType::Custom(path) => Self::Custom(assets.load(path))
         }
@@ -108,51 +195,162 @@ impl Model {
         }
     }
 
-    /// Get meshable block texture if exists
-    pub fn texture(&self) -> Option<Handle<Image>> {
+    /// Is model drawn as two diagonal quads?
+    pub fn is_crossed(&self) -> bool {
+        matches!(self, Self::Crossed(_))
+    }
+
+    /// Face textures of meshable or crossed model
+    pub fn textures(&self) -> Vec<Handle<Image>> {
         match self {
-            Self::Meshable(h) => Some(h.clone()),
-            _ => None
+            Self::Meshable(faces) => faces.to_vec(),
+            Self::Crossed(h) => vec![h.clone()],
+            _ => Vec::new()
         }
     }
 }
 
 // Block data like model, collision, etc
+#[derive(Clone)]
 pub struct Block {
     pub model: Model,
-    pub collision: Option<CollisionBox>
+    pub collision: Option<CollisionBox>,
+    pub opacity: Opacity,
+    /// Hides neighbours faces and darkens their corners
+    pub occludes: bool,
+    /// Texture layer of every face in Direction::to_u32 order
+    pub faces: [u32; 6],
+    /// Loaded custom model data
+    pub baked: Option<Arc<BakedModel>>
 }
 
 impl Block {
     pub fn new(assets: &AssetServer, t: BlockType) -> Self {
         Self {
+            occludes: t.occludes(),
             model: Model::load(assets, t.model),
-            collision: t.collision
+            collision: t.collision,
+            opacity: t.opacity,
+            faces: [0; 6],
+            baked: None
+        }
+    }
+}
+
+/// Texture layer of handle, same textures share one layer
+fn layer(textures: &mut Vec<Handle<Image>>, handle: &Handle<Image>) -> u32 {
+    match textures.iter().position(|t| t == handle) {
+        Some(i) => i as u32,
+        None => {
+            textures.push(handle.clone());
+            textures.len() as u32 - 1
         }
     }
 }
 
 #[derive(Clone)]
 /// Contains all blocks assets
-pub struct BlocksHandler(Arc<OrderMap<String, Block>>);
+pub struct BlocksHandler(Arc<OrderMap<String, Block>>, Arc<Vec<Handle<Image>>>);
 
 impl BlocksHandler {
     pub fn new(assets: &AssetServer, blocks: Blocks) -> Self {
-        let data = blocks.0.into_iter()
-            .map(|(name, t)| (name, Block::new(assets, t)));
+        let mut textures = Vec::new();
+        let data = blocks.0.into_iter().map(|(name, t)| {
+            let mut block = Block::new(assets, t);
+            match &block.model {
+                Model::Meshable(faces) => block.faces = faces.each_ref().map(|h| layer(&mut textures, h)),
+                Model::Crossed(h) => block.faces = [layer(&mut textures, h); 6],
+                _ => ()
+            }
+            (name, block)
+        }).collect::<Vec<_>>();
+
+        Self(Arc::new(OrderMap::from_iter(data)), Arc::new(textures)).validate()
+    }
 
-        Self(Arc::new(OrderMap::from_iter(data)))
+    /// Warn if ids don't fit chunk data (u16) or vertex texture layer (16 bits)
+    fn validate(self) -> Self {
+        let max = u16::MAX as usize + 1;
+        if self.0.len() > max {
+            println!("Too many blocks: {}, max is {}", self.0.len(), max);
+        }
+        if self.1.len() > max {
+            println!("Too many block textures: {}, max is {}", self.1.len(), max);
+        }
+        self
     }
 
     /// Return block id (0 if not exists) by name
     pub fn block(&self, name: impl Into<String>) -> u16 {
-        self.0.get_index_of(&name.into())
-        .and_then(|i| Some(i as u16)).unwrap_or(0)
+        self.get(name).unwrap_or(0)
+    }
+
+    /// Return block id by name if exists
+    pub fn get(&self, name: impl Into<String>) -> Option<u16> {
+        self.0.get_index_of(&name.into()).map(|i| i as u16)
     }
 
-    /// Get all meshable blocks textures
-    pub fn textures(&self) -> Vec<Option<Handle<Image>>> {
-        self.0.iter().map(|(_, b)| b.model.texture()).collect()
+    /// All blocks names in id order
+    pub fn names(&self) -> Vec<String> {
+        self.0.keys().cloned().collect()
+    }
+
+    /// Custom models handles
+    pub fn models(&self) -> Vec<Handle<CustomModel>> {
+        self.0.values().filter_map(|b| match &b.model {
+            Model::Custom(h) => Some(h.clone()),
+            _ => None
+        }).collect()
+    }
+
+    /// Bake loaded custom models, their textures share layers with blocks textures
+    pub fn bake(&self, models: &Assets<CustomModel>) -> Self {
+        // Same layers as in new, custom textures from previous bake are dropped
+        let mut textures = Vec::new();
+        for handle in self.0.values().flat_map(|b| b.model.textures()) {
+            layer(&mut textures, &handle);
+        }
+
+        let data = self.0.iter().map(|(name, block)| {
+            let mut block = block.clone();
+            if let Model::Custom(handle) = &block.model {
+                block.baked = models.get(handle).map(|m| {
+                    let layers: Vec<_> = m.textures.iter().map(|h| layer(&mut textures, h)).collect();
+                    Arc::new(m.bake(&layers))
+                });
+            }
+
+            (name.clone(), block)
+        }).collect::<Vec<_>>();
+
+        Self(Arc::new(OrderMap::from_iter(data)), Arc::new(textures)).validate()
+    }
+
+    /// All unique textures, index is texture layer
+    pub fn textures(&self) -> Vec<Handle<Image>> {
+        self.1.to_vec()
+    }
+
+    /// Texture layer of block face
+    pub fn texture(&self, block: u16, dir: Direction) -> u32 {
+        match self.0.get_index(block as usize) {
+            Some((_, t)) => t.faces[dir.to_u32() as usize],
+            _ => 0
+        }
+    }
+
+    /// Texture for block icon: side face of meshable or crossed texture
+    pub fn icon(&self, block: u16) -> Option<Handle<Image>> {
+        match &self.0.get_index(block as usize)?.1.model {
+            Model::Meshable(faces) => Some(faces[Direction::Forward.to_u32() as usize].clone()),
+            Model::Crossed(h) => Some(h.clone()),
+            _ => None
+        }
+    }
+
+    /// Get baked custom model
+    pub fn model(&self, block: u16) -> Option<&BakedModel> {
+        self.0.get_index(block as usize).and_then(|(_, b)| b.baked.as_deref())
     }
 
     /// Is texture drawable with default way?
@@ -163,8 +361,59 @@ impl BlocksHandler {
         }
     }
 
+    /// Block faces opacity
+    pub fn opacity(&self, block: u16) -> Opacity {
+        match self.0.get_index(block as usize) {
+            Some((_, t)) => t.opacity,
+            _ => Opacity::Opaque
+        }
+    }
+
+    /// Does block hide neighbours faces? (separate from being drawn)
+    pub fn is_occluder(&self, block: u16) -> bool {
+        match self.0.get_index(block as usize) {
+            Some((_, t)) => t.occludes,
+            _ => false
+        }
+    }
+
+    /// Is block drawn as crossed quads?
+    pub fn is_crossed(&self, block: u16) -> bool {
+        match self.0.get_index(block as usize) {
+            Some((_, t)) => t.model.is_crossed(),
+            _ => false
+        }
+    }
+
     /// Returns all blocks vec
     pub fn all(&self) -> Vec<u16> {
         self.0.keys().enumerate().map(|(i, _)| i as u16).collect()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+impl BlocksHandler {
+    /// Handler without assets: block 0 is air, others are meshable blocks of opacity.
+    /// Block texture layers are block ids
+    pub fn test(opacities: &[Opacity]) -> Self {
+        let air = Block {
+            model: Model::Empty,
+            collision: None,
+            opacity: Opacity::Opaque,
+            occludes: false,
+            faces: [0; 6],
+            baked: None
+        };
+        let blocks = opacities.iter().enumerate().map(|(i, opacity)| Block {
+            model: Model::Meshable(std::array::from_fn(|_| Handle::default())),
+            collision: Some(CollisionBox::default()),
+            opacity: *opacity,
+            occludes: *opacity == Opacity::Opaque,
+            faces: [i as u32 + 1; 6],
+            baked: None
+        });
+
+        let data = std::iter::once(air).chain(blocks).enumerate().map(|(i, b)| (i.to_string(), b));
+        Self(Arc::new(OrderMap::from_iter(data)), Arc::new(Vec::new()))
+    }
+}
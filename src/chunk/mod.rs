@@ -1,10 +1,14 @@
 //! Main chunks objects data;
 
 mod blocks;
+mod model;
+mod raycast;
 use std::sync::*;
 use bevy::prelude::*;
 use rand::seq::SliceRandom;
 pub use blocks::*;
+pub use model::*;
+pub use raycast::*;
 
 fn _random<T>(vec: &Vec<T>) -> &T {
     vec.choose(&mut rand::thread_rng()).unwrap()
@@ -34,6 +38,12 @@ impl RawChunk {
         })
     }
 
+    /// Split global block pos into chunk pos and relative block pos
+    pub fn split(pos: IVec3) -> (IVec3, IVec3) {
+        let size = IVec3::splat(Self::SIZE_I32);
+        (pos.div_euclid(size), pos.rem_euclid(size))
+    }
+
     /// XZY coord system
     pub fn block_index(pos: IVec3) -> usize {
         let x = pos.x % Self::SIZE_I32;
@@ -43,6 +53,12 @@ impl RawChunk {
         (x + y + z) as usize
     }
 
+    /// Inverse of block_index
+    pub fn block_pos(index: usize) -> IVec3 {
+        let i = index as i32;
+        IVec3::new(i % Self::SIZE_I32, i / Self::SIZE_I32.pow(2), (i / Self::SIZE_I32) % Self::SIZE_I32)
+    }
+
     /// Main generate function - WIP
     pub async fn generate(_blocks: BlocksHandler, pos: IVec3) -> Self {
         if pos.y == 0 {
@@ -57,25 +73,6 @@ impl RawChunk {
         }
     }
 
-    /// Get all blocks above cursore by radius, absolute pos and vector u (camera forward)
-    pub fn under_cursor(mut current: Vec3, u: Vec3, r: usize) -> Vec<Vec3> {        
-        let delta = 0.01;
-        let mut stored = Vec::with_capacity(r);
-        let mut blocks = Vec::with_capacity(r);
-        while blocks.len() < r {
-            current.x += u.x*delta;
-            current.y += u.y*delta;
-            current.z += u.z*delta;
-
-            let next = current.as_ivec3();
-            if !stored.contains(&next) {
-                stored.push(next);
-                blocks.push(current);
-            }
-        }
-        blocks
-    }
-
     /// Create a chunk filled with block
     pub fn filled(block: u16) -> Self {
         Self(std::iter::repeat_n(block, Self::SIZE_P3).collect())
@@ -111,21 +108,24 @@ impl Chunk {
 
 #[derive(Debug, Clone)]
 #[repr(transparent)]
-/// Contains all near chunks:
+/// Contains current chunk and all 26 near chunks (diagonals are needed for AO)
 /// 
-/// Current; Left; Right; Down; Up; Back; Forward;
-pub struct ChunksRefs([Chunk; 7]);
+/// Chunks are stored in 3x3x3 XYZ order, current is in the middle.
+/// Meshing samples blocks from PaddedChunk copy
+pub struct ChunksRefs([Chunk; 27]);
+
+const fn refs_offsets() -> [IVec3; 27] {
+    let mut result = [IVec3::ZERO; 27];
+    let mut i = 0;
+    while i < 27 {
+        result[i] = IVec3::new((i % 3) as i32 - 1, (i / 3 % 3) as i32 - 1, (i / 9) as i32 - 1);
+        i += 1;
+    }
+    result
+}
 
 impl ChunksRefs {
-    pub const OFFSETS: [IVec3; 7] = [
-        IVec3::ZERO,  // current
-        IVec3::NEG_Y, // down
-        IVec3::Y,     // up
-        IVec3::NEG_X, // left
-        IVec3::X,     // right
-        IVec3::NEG_Z, // forward
-        IVec3::Z,     // back
-    ];
+    pub const OFFSETS: [IVec3; 27] = refs_offsets();
 
     pub const SIZE: usize = RawChunk::SIZE;
     pub const SIZE_I32: i32 = RawChunk::SIZE_I32;
@@ -134,41 +134,121 @@ impl ChunksRefs {
         Self::OFFSETS.iter().map(|o| pos + o).collect()
     }
 
-    pub fn new(data: [Chunk; 7]) -> Self {
+    pub fn new(data: [Chunk; 27]) -> Self {
         Self(data)
     }
 
-    fn offset_index(v: IVec3) -> usize {
-        Self::OFFSETS.iter().position(|p| p==&v).unwrap()
+    /// Current chunk
+    pub fn center(&self) -> &Chunk {
+        &self.0[13]
     }
+}
+
+/// Copy of chunk with one block border from all 26 neighbours (34^3),
+/// taken once for meshing, so samples don't lock chunks
+#[derive(Debug, Clone)]
+pub struct PaddedChunk(Vec<u16>);
+
+impl PaddedChunk {
+    pub const SIZE: usize = RawChunk::SIZE + 2;
+    pub const SIZE_I32: i32 = Self::SIZE as i32;
 
-    fn chunk_index(x: usize, y: usize, z: usize) -> usize {
-        let (cx, cy, cz) = (
-            (x / Self::SIZE) as i32,
-            (y / Self::SIZE) as i32, 
-            (z / Self::SIZE) as i32
-        );
-        
-        Self::offset_index(IVec3::new(cx, cy, cz) - IVec3::ONE)
+    pub fn new(refs: &ChunksRefs) -> Self {
+        let size = RawChunk::SIZE_I32;
+        let mut data = vec![0u16; Self::SIZE.pow(3)];
+
+        // Padded coords covered by neighbour on axis
+        let range = |offset: i32| match offset {
+            -1 => 0..1,
+            0 => 1..size + 1,
+            _ => size + 1..size + 2
+        };
+
+        for (chunk, offset) in refs.0.iter().zip(ChunksRefs::OFFSETS) {
+            let guard = chunk.read();
+            let blocks = guard.get();
+            let origin = IVec3::ONE + offset * size;
+
+            for y in range(offset.y) {
+                for z in range(offset.z) {
+                    // Rows are continuous by X in both layouts
+                    let xs = range(offset.x);
+                    let from = RawChunk::block_index(IVec3::new(xs.start, y, z) - origin);
+                    let to = Self::index(IVec3::new(xs.start, y, z));
+                    let len = xs.len();
+                    data[to..to + len].copy_from_slice(&blocks[from..from + len]);
+                }
+            }
+        }
+
+        Self(data)
     }
-    
-    fn block_index(x: usize, y: usize, z: usize) -> usize {
-        let (bx, by, bz) = (
-            (x % Self::SIZE) as i32,
-            (y % Self::SIZE) as i32,
-            (z % Self::SIZE) as i32
-        );
 
-        RawChunk::block_index(IVec3::new(bx, by, bz))
+    fn index(padded: IVec3) -> usize {
+        (padded.x + padded.z * Self::SIZE_I32 + padded.y * Self::SIZE_I32.pow(2)) as usize
     }
 
-    pub fn get_block(&self, pos: IVec3) -> u16 {
-        let x = (pos.x + Self::SIZE_I32) as usize;
-        let y = (pos.y + Self::SIZE_I32) as usize;
-        let z = (pos.z + Self::SIZE_I32) as usize;
-        let chunk = Self::chunk_index(x, y, z);
-        let block = Self::block_index(x, y, z);
+    /// Block by chunk local pos (-1..=32 on every axis)
+    #[inline]
+    pub fn get(&self, pos: IVec3) -> u16 {
+        self.0[Self::index(pos + IVec3::ONE)]
+    }
 
-        self.0[chunk].read().get()[block]
+    /// Replace neighbour blocks behind chunk side (unit normal) with air
+    pub fn clear_side(&mut self, normal: IVec3) {
+        let layer = if normal.max_element() > 0 { Self::SIZE_I32 - 1 } else { 0 };
+        for a in 0..Self::SIZE_I32 {
+            for b in 0..Self::SIZE_I32 {
+                let padded = match normal.abs() {
+                    IVec3::X => IVec3::new(layer, a, b),
+                    IVec3::Y => IVec3::new(a, layer, b),
+                    _ => IVec3::new(a, b, layer)
+                };
+                self.0[Self::index(padded)] = 0;
+            }
+        }
     }
-}
\ No newline at end of file
+
+    /// Center chunk blocks in ys layers with local pos, layers are contiguous in YZX order
+    pub fn blocks(&self, ys: std::ops::Range<i32>) -> impl Iterator<Item = (IVec3, u16)> + '_ {
+        let layer = RawChunk::SIZE.pow(2);
+        (ys.start as usize * layer..ys.end as usize * layer).map(move |i| {
+            let pos = RawChunk::block_pos(i);
+            (pos, self.get(pos))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+    use super::*;
+
+    /// Per-voxel lookup used before PaddedChunk: find chunk and lock it for every block
+    fn locked_get(refs: &ChunksRefs, pos: IVec3) -> u16 {
+        let (chunk, local) = RawChunk::split(pos);
+        let index = ChunksRefs::OFFSETS.iter().position(|o| *o == chunk).unwrap();
+        refs.0[index].read().get()[RawChunk::block_index(local)]
+    }
+
+    fn random_refs(seed: u64) -> ChunksRefs {
+        let mut rng = StdRng::seed_from_u64(seed);
+        ChunksRefs::new(std::array::from_fn(|_| {
+            let mut chunk = RawChunk::empty();
+            chunk.get_mut().iter_mut().for_each(|b| *b = rng.gen_range(0..4));
+            Chunk::new(chunk)
+        }))
+    }
+
+    #[test]
+    fn padded_matches_refs() {
+        let refs = random_refs(41);
+        let padded = PaddedChunk::new(&refs);
+
+        for i in 0..PaddedChunk::SIZE.pow(3) {
+            let s = PaddedChunk::SIZE;
+            let pos = IVec3::new((i % s) as i32, (i / s / s) as i32, (i / s % s) as i32) - IVec3::ONE;
+            assert_eq!(padded.get(pos), locked_get(&refs, pos), "{}", pos);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Custom block models: cuboid elements with per-face textures and UVs
+//!
+//! Model file (`*.model.yaml` or `*.model.json`) example:
+//! ```yaml
+//! textures:
+//!   top: slab_top.png
+//!   side: slab_side.png
+//! elements:
+//!   - from: [0, 0, 0]
+//!     to: [16, 8, 16]
+//!     faces:
+//!       up: { texture: top }
+//!       down: { texture: top, cullface: down }
+//!       left: { texture: side, uv: [0, 8, 16, 16], cullface: left }
+//! ```
+//! Coords and UVs are in 1/16 of block. Textures are single images,
+//! they share material layers with blocks textures.
+
+use bevy::{
+    asset::*,
+    prelude::*,
+};
+use serde::{Serialize, Deserialize};
+use ordermap::OrderMap;
+use crate::mesher::Direction;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelFace {
+    /// Texture name from model textures ("#name" is allowed)
+    pub texture: String,
+    /// [u0, v0, u1, v1], made from position if not set
+    #[serde(default)]
+    pub uv: Option<[u32; 4]>,
+    /// Skip face if neighbour in this direction is full block
+    #[serde(default)]
+    pub cullface: Option<Direction>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelElement {
+    pub from: IVec3,
+    pub to: IVec3,
+    pub faces: OrderMap<Direction, ModelFace>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelData {
+    /// Texture name -> asset path
+    pub textures: OrderMap<String, String>,
+    pub elements: Vec<ModelElement>
+}
+
+/// Custom model asset
+#[derive(Clone, Asset, TypePath)]
+pub struct CustomModel {
+    pub data: ModelData,
+    /// Loaded textures in ModelData::textures order
+    #[dependency]
+    pub textures: Vec<Handle<Image>>
+}
+
+impl CustomModel {
+    /// Resolve face textures into material layers (layer of every model texture)
+    pub fn bake(&self, layers: &[u32]) -> BakedModel {
+        let elements = self.data.elements.iter().map(|e| {
+            let faces = e.faces.iter().filter_map(|(dir, face)| {
+                let name = face.texture.trim_start_matches('#');
+                let Some(index) = self.data.textures.get_index_of(name) else {
+                    println!("Unknown model texture {}", face.texture);
+                    return None;
+                };
+
+                Some(BakedFace {
+                    dir: *dir,
+                    uv: face.uv,
+                    texture: layers[index],
+                    cullface: face.cullface
+                })
+            }).collect();
+
+            // Corners are normalized, so from <= to on every axis
+            BakedElement {
+                from: e.from.min(e.to).clamp(IVec3::ZERO, IVec3::splat(16)),
+                to: e.from.max(e.to).clamp(IVec3::ZERO, IVec3::splat(16)),
+                faces
+            }
+        }).collect();
+
+        BakedModel { elements, textures: self.textures.clone() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BakedFace {
+    pub dir: Direction,
+    pub uv: Option<[u32; 4]>,
+    /// Texture layer in material
+    pub texture: u32,
+    pub cullface: Option<Direction>
+}
+
+#[derive(Debug, Clone)]
+pub struct BakedElement {
+    pub from: IVec3,
+    pub to: IVec3,
+    pub faces: Vec<BakedFace>
+}
+
+/// Custom model ready for meshing
+#[derive(Debug, Clone)]
+pub struct BakedModel {
+    pub elements: Vec<BakedElement>,
+    pub textures: Vec<Handle<Image>>
+}
+
+#[derive(Default)]
+pub struct CustomModelLoader;
+impl AssetLoader for CustomModelLoader {
+    type Asset = CustomModel;
+    type Error = String;
+    type Settings = ();
+
+    async fn load(
+            &self,
+            reader: &mut dyn io::Reader,
+            _settings: &Self::Settings,
+            load_context: &mut LoadContext<'_>,
+        ) -> Result<Self::Asset, Self::Error> {
+        let mut buf = String::with_capacity(512);
+        reader.read_to_string(&mut buf).await.map_err(|e| e.to_string())?;
+
+        // Yaml parser reads json as well
+        let data: ModelData = serde_yaml::from_str(&buf).map_err(|e| e.to_string())?;
+        let textures = data.textures.values().map(|path| load_context.load(path.clone())).collect();
+
+        Ok(CustomModel { data, textures })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["model.yaml", "model.json"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Module example with an element given by swapped corners
+    const MODEL: &str = "
+textures:
+  top: slab_top.png
+  side: slab_side.png
+elements:
+  - from: [0, 0, 0]
+    to: [16, 8, 16]
+    faces:
+      up: { texture: top }
+      down: { texture: top, cullface: down }
+      left: { texture: side, uv: [0, 8, 16, 16], cullface: left }
+  - from: [12, 20, 4]
+    to: [4, 8, 12]
+    faces:
+      up: { texture: '#side' }
+      back: { texture: missing }
+";
+
+    #[test]
+    fn parse_and_bake() {
+        let data: ModelData = serde_yaml::from_str(MODEL).unwrap();
+        let model = CustomModel { textures: vec![Handle::default(); data.textures.len()], data };
+        let baked = model.bake(&[5, 7]);
+
+        let [slab, swapped] = &baked.elements[..] else { panic!("Wrong elements count") };
+        assert_eq!((slab.from, slab.to), (IVec3::ZERO, IVec3::new(16, 8, 16)));
+        let faces: Vec<_> = slab.faces.iter().map(|f| (f.dir, f.texture, f.uv, f.cullface)).collect();
+        assert_eq!(faces, [
+            (Direction::Up, 5, None, None),
+            (Direction::Down, 5, None, Some(Direction::Down)),
+            (Direction::Left, 7, Some([0, 8, 16, 16]), Some(Direction::Left))
+        ]);
+
+        // Corners are sorted and clamped, unknown texture face is skipped
+        assert_eq!((swapped.from, swapped.to), (IVec3::new(4, 8, 4), IVec3::new(12, 16, 12)));
+        assert_eq!(swapped.faces.len(), 1);
+        assert_eq!((swapped.faces[0].dir, swapped.faces[0].texture), (Direction::Up, 7));
+    }
+}
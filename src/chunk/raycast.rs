@@ -0,0 +1,219 @@
+use bevy::prelude::*;
+
+/// Voxel ray step: voxel pos, entered face normal and distance from origin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayStep {
+    pub voxel: IVec3,
+    /// Normal of the face the ray entered through (ZERO for the origin voxel)
+    pub normal: IVec3,
+    pub distance: f32
+}
+
+/// Amanatides–Woo voxel traversal: visits every voxel crossed by the ray in order
+#[derive(Debug, Clone)]
+pub struct VoxelRay {
+    current: Option<RayStep>,
+    step: IVec3,
+    t_max: Vec3,
+    t_delta: Vec3
+}
+
+impl VoxelRay {
+    pub fn new(origin: Vec3, dir: Vec3) -> Self {
+        let dir = dir.normalize_or_zero();
+        let voxel = origin.floor().as_ivec3();
+        let sign = |d: f32| if d > 0.0 { 1 } else if d < 0.0 { -1 } else { 0 };
+        let step = IVec3::new(sign(dir.x), sign(dir.y), sign(dir.z));
+
+        let axis = |o: f32, v: i32, d: f32| -> (f32, f32) {
+            if d > 0.0 {
+                ((v as f32 + 1.0 - o) / d, 1.0 / d)
+            } else if d < 0.0 {
+                ((o - v as f32) / -d, -1.0 / d)
+            } else {
+                (f32::INFINITY, f32::INFINITY)
+            }
+        };
+
+        let (x, y, z) = (
+            axis(origin.x, voxel.x, dir.x),
+            axis(origin.y, voxel.y, dir.y),
+            axis(origin.z, voxel.z, dir.z)
+        );
+
+        Self {
+            current: Some(RayStep { voxel, normal: IVec3::ZERO, distance: 0.0 }),
+            step,
+            t_max: Vec3::new(x.0, y.0, z.0),
+            t_delta: Vec3::new(x.1, y.1, z.1)
+        }
+    }
+}
+
+impl Iterator for VoxelRay {
+    type Item = RayStep;
+
+    fn next(&mut self) -> Option<RayStep> {
+        let result = self.current?;
+
+        // Advance along the axis with nearest boundary
+        let axis = if self.t_max.x < self.t_max.y {
+            if self.t_max.x < self.t_max.z { 0 } else { 2 }
+        } else if self.t_max.y < self.t_max.z { 1 } else { 2 };
+
+        let distance = self.t_max[axis];
+        self.current = if distance.is_finite() {
+            let mut voxel = result.voxel;
+            let mut normal = IVec3::ZERO;
+            voxel[axis] += self.step[axis];
+            normal[axis] = -self.step[axis];
+            self.t_max[axis] += self.t_delta[axis];
+
+            Some(RayStep { voxel, normal, distance })
+        } else {
+            // Zero direction: only origin voxel
+            None
+        };
+
+        Some(result)
+    }
+}
+
+/// Raycast result
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    /// Global hit block pos
+    pub block: IVec3,
+    /// Hit face normal
+    pub normal: IVec3,
+    pub distance: f32,
+    /// Hit block id
+    pub data: u16
+}
+
+impl RayHit {
+    /// Empty block pos in front of hit face
+    pub fn adjacent(&self) -> IVec3 {
+        self.block + self.normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voxels(ray: VoxelRay, n: usize) -> Vec<IVec3> {
+        ray.take(n).map(|s| s.voxel).collect()
+    }
+
+    #[test]
+    fn negative_origin() {
+        let mut ray = VoxelRay::new(Vec3::new(-0.5, -31.9, 0.2), Vec3::X);
+        assert_eq!(ray.next().unwrap(), RayStep { voxel: IVec3::new(-1, -32, 0), normal: IVec3::ZERO, distance: 0.0 });
+
+        let step = ray.next().unwrap();
+        assert_eq!((step.voxel, step.normal), (IVec3::new(0, -32, 0), IVec3::NEG_X));
+        assert!((step.distance - 0.5).abs() < 1e-5);
+
+        let mut ray = VoxelRay::new(Vec3::new(-0.5, -31.9, 0.2), Vec3::NEG_Y);
+        ray.next();
+        let step = ray.next().unwrap();
+        assert_eq!((step.voxel, step.normal), (IVec3::new(-1, -33, 0), IVec3::Y));
+        assert!((step.distance - 0.1).abs() < 1e-5);
+    }
+
+    #[test]
+    fn negative_axes() {
+        let origin = Vec3::new(0.5, -0.5, 31.25);
+        for dir in [Vec3::NEG_X, Vec3::NEG_Y, Vec3::NEG_Z] {
+            let start = origin.floor().as_ivec3();
+            let steps: Vec<_> = VoxelRay::new(origin, dir).take(4).collect();
+            let offset = origin - origin.floor();
+
+            for (i, step) in steps.iter().enumerate() {
+                assert_eq!(step.voxel, start + dir.as_ivec3() * i as i32);
+                if i == 0 { continue; }
+
+                assert_eq!(step.normal, -dir.as_ivec3());
+                let expected = offset.dot(-dir) + (i - 1) as f32;
+                assert!((step.distance - expected).abs() < 1e-5, "{:?} {}", dir, step.distance);
+            }
+        }
+    }
+
+    #[test]
+    fn diagonal_coverage() {
+        let origins = [Vec3::new(-0.3, -31.9, 2.7), Vec3::new(5.5, 0.01, -7.25), Vec3::new(0.0, 0.0, 0.0)];
+        let dirs = [
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(-1.0, 0.5, 0.25),
+            Vec3::new(0.3, -1.0, -0.7),
+            Vec3::new(-0.9, -0.2, 1.0),
+            Vec3::new(1.0, 0.0, -1.0),
+        ];
+
+        for origin in origins {
+            for dir in dirs {
+                let steps: Vec<_> = VoxelRay::new(origin, dir).take_while(|s| s.distance < 20.0).collect();
+
+                // Neighbour voxels share a face, distance grows
+                for pair in steps.windows(2) {
+                    let delta = pair[1].voxel - pair[0].voxel;
+                    assert_eq!(delta.abs().element_sum(), 1);
+                    assert_eq!(pair[1].normal, -delta);
+                    assert!(pair[1].distance >= pair[0].distance);
+                }
+
+                // Every voxel on the segment is visited
+                let dir = dir.normalize();
+                for i in 0..2000 {
+                    let point = origin + dir * (i as f32 * 0.01);
+                    let voxel = point.floor().as_ivec3();
+                    // Skip points too close to a boundary to be floored reliably
+                    if (point - point.round()).abs().min_element() < 1e-4 { continue; }
+                    assert!(steps.iter().any(|s| s.voxel == voxel), "{:?} {:?} missed {:?}", origin, dir, voxel);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn boundary_origin() {
+        // Origin on voxel boundary belongs to the voxel above it
+        let origin = Vec3::new(1.0, -2.0, 0.5);
+        assert_eq!(voxels(VoxelRay::new(origin, Vec3::X), 3), [IVec3::new(1, -2, 0), IVec3::new(2, -2, 0), IVec3::new(3, -2, 0)]);
+
+        // Going back it leaves origin voxel at once
+        let steps: Vec<_> = VoxelRay::new(origin, Vec3::NEG_X).take(2).collect();
+        assert_eq!(steps[1].voxel, IVec3::new(0, -2, 0));
+        assert_eq!(steps[1].distance, 0.0);
+
+        let steps: Vec<_> = VoxelRay::new(origin, Vec3::new(-1.0, -1.0, 0.0)).take(3).collect();
+        assert_eq!(steps[0].voxel, IVec3::new(1, -2, 0));
+        assert_eq!(steps[1].distance, 0.0);
+        assert_eq!(steps[2].distance, 0.0);
+        assert_eq!(steps[2].voxel, IVec3::new(0, -3, 0));
+    }
+
+    #[test]
+    fn zero_direction() {
+        assert_eq!(voxels(VoxelRay::new(Vec3::new(-0.5, 3.2, 7.9), Vec3::ZERO), 5), [IVec3::new(-1, 3, 7)]);
+    }
+
+    #[test]
+    fn first_hit() {
+        // Wall at x = 3 and floor at y = -1
+        let solid = |v: IVec3| v.x == 3 || v.y == -1;
+        let hit = |origin: Vec3, dir: Vec3| VoxelRay::new(origin, dir).find(|s| solid(s.voxel)).unwrap();
+
+        let dir = Vec3::new(1.0, 0.2, 0.0);
+        let step = hit(Vec3::new(0.5, 0.5, 0.5), dir);
+        assert_eq!((step.voxel, step.normal), (IVec3::new(3, 0, 0), IVec3::NEG_X));
+        assert!((step.distance - 2.5 / dir.normalize().x).abs() < 1e-5);
+
+        let dir = Vec3::new(0.5, -1.0, -0.25);
+        let step = hit(Vec3::new(0.5, 1.5, 0.5), dir);
+        assert_eq!((step.voxel, step.normal), (IVec3::new(1, -1, 0), IVec3::Y));
+        assert!((step.distance - 1.5 / -dir.normalize().y).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,168 @@
+//! Cave culling: chunks hidden behind solid blocks aren't rendered
+
+use std::collections::VecDeque;
+use bevy::prelude::*;
+use strum::IntoEnumIterator;
+use super::*;
+
+/// Chunk faces connected through non-occluding blocks.
+/// Mask of connected faces for every face, bits are Direction::to_u32
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkVisibility([u8; 6]);
+
+impl ChunkVisibility {
+    /// Every face sees every other (not built chunks)
+    pub const OPEN: Self = Self([0b111111; 6]);
+
+    /// Flood fill center chunk non-occluding blocks, every filled area connects faces it touches
+    pub fn new(handler: &BlocksHandler, chunk: &PaddedChunk) -> Self {
+        let size = RawChunk::SIZE_I32;
+        let mut visited = vec![false; RawChunk::SIZE_P3];
+        let mut stack = Vec::new();
+        let mut result = [0u8; 6];
+
+        for start in 0..RawChunk::SIZE_P3 {
+            if visited[start] || handler.is_occluder(chunk.get(RawChunk::block_pos(start))) { continue; }
+
+            let mut faces = 0u8;
+            visited[start] = true;
+            stack.push(start);
+            while let Some(index) = stack.pop() {
+                let pos = RawChunk::block_pos(index);
+                for dir in Direction::iter() {
+                    let next = pos + dir.air_sample();
+                    if next.cmplt(IVec3::ZERO).any() || next.cmpge(IVec3::splat(size)).any() {
+                        faces |= 1 << dir.to_u32();
+                        continue;
+                    }
+
+                    let next_index = RawChunk::block_index(next);
+                    if visited[next_index] || handler.is_occluder(chunk.get(next)) { continue; }
+                    visited[next_index] = true;
+                    stack.push(next_index);
+                }
+            }
+
+            for (face, connected) in result.iter_mut().enumerate() {
+                if faces >> face & 1 == 1 {
+                    *connected |= faces;
+                }
+            }
+        }
+
+        Self(result)
+    }
+
+    /// Can be seen through from one face to other
+    pub fn connected(&self, from: Direction, to: Direction) -> bool {
+        self.0[from.to_u32() as usize] >> to.to_u32() & 1 == 1
+    }
+}
+
+/// Flood fill loaded chunks from camera chunk through connected faces, hide unreachable meshes.
+/// Fill never goes back against any direction it went, so it doesn't wrap around walls.
+/// Chunk is entered once per face, other face may connect to other faces
+pub fn cull(
+    controller: Res<Controller>,
+    mut sections: Query<Mut<Visibility>, With<ChunkQuads>>
+) {
+    let mut reachable = HashSet::new();
+    if controller.culling {
+        let camera = controller.camera;
+        let mut queue = VecDeque::from([(camera, None::<Direction>, 0u8)]);
+        let mut visited = HashSet::new();
+        reachable.insert(camera);
+
+        while let Some((pos, from, travelled)) = queue.pop_front() {
+            let visibility = controller.visibility.get(&pos).unwrap_or(&ChunkVisibility::OPEN);
+            for dir in Direction::iter() {
+                let next = pos + dir.air_sample();
+                if travelled >> dir.opposite().to_u32() & 1 == 1 { continue; }
+                if from.is_some_and(|from| !visibility.connected(from, dir)) { continue; }
+                if !controller.chunks.contains_key(&next) || !visited.insert((next, dir.opposite())) { continue; }
+
+                reachable.insert(next);
+                queue.push_back((next, Some(dir.opposite()), travelled | 1 << dir.to_u32()));
+            }
+        }
+    }
+
+    for (pos, entities) in controller.meshes.iter() {
+        let visibility = match !controller.culling || reachable.contains(pos) {
+            true => Visibility::Inherited,
+            false => Visibility::Hidden
+        };
+        for entity in entities.iter().flatten().flatten() {
+            if let Ok(mut current) = sections.get_mut(*entity) {
+                current.set_if_neq(visibility);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+    use super::*;
+
+    /// Padded chunk with center blocks by local pos and empty neighbours
+    fn padded(block: impl Fn(IVec3) -> u16) -> PaddedChunk {
+        let chunks = ChunksRefs::OFFSETS.map(|offset| {
+            let mut chunk = RawChunk::empty();
+            if offset == IVec3::ZERO {
+                chunk.get_mut().iter_mut().enumerate().for_each(|(i, b)| *b = block(RawChunk::block_pos(i)));
+            }
+            Chunk::new(chunk)
+        });
+        PaddedChunk::new(&ChunksRefs::new(chunks))
+    }
+
+    #[test]
+    fn visibility() {
+        let handler = BlocksHandler::test(&[Opacity::Opaque, Opacity::Cutout]);
+        assert_eq!(ChunkVisibility::new(&handler, &padded(|_| 0)), ChunkVisibility::OPEN);
+        assert_eq!(ChunkVisibility::new(&handler, &padded(|_| 1)), ChunkVisibility([0; 6]));
+        assert_eq!(ChunkVisibility::new(&handler, &padded(|_| 2)), ChunkVisibility::OPEN);
+
+        // Solid floor splits chunk into upper and lower halves
+        let floor = ChunkVisibility::new(&handler, &padded(|pos| (pos.y == 16) as u16));
+        assert!(!floor.connected(Direction::Up, Direction::Down));
+        assert!(floor.connected(Direction::Up, Direction::Left) && floor.connected(Direction::Down, Direction::Left));
+        assert!(floor.connected(Direction::Left, Direction::Right));
+    }
+
+    #[test]
+    fn cull_entry_faces() {
+        let mut world = World::new();
+        let mut controller = Controller::default();
+        let (a, b, c, target) = (IVec3::X, IVec3::new(1, 1, 0), IVec3::Y, IVec3::new(2, 1, 0));
+        for pos in [IVec3::ZERO, a, b, c, target] {
+            controller.chunks.insert(pos, Chunk::new(RawChunk::empty()));
+            let section = ChunkQuads { buffer: Handle::default(), quads: 0, opacity: Opacity::Opaque, center: Vec3::ZERO };
+            let entity = world.spawn((section, Visibility::default())).id();
+            let mut entities = [[None; 3]; SECTIONS];
+            entities[0][0] = Some(entity);
+            controller.meshes.insert(pos, entities);
+        }
+
+        // B connects only left and right faces: target is seen through it from C, not from A below
+        let sides = 1 << Direction::Left.to_u32() | 1 << Direction::Right.to_u32();
+        let mut faces = [0; 6];
+        faces[Direction::Left.to_u32() as usize] = sides;
+        faces[Direction::Right.to_u32() as usize] = sides;
+        controller.visibility.insert(b, ChunkVisibility(faces));
+        let entity = |controller: &Controller, pos: IVec3| controller.meshes[&pos][0][0].unwrap();
+        let (far, near) = (entity(&controller, target), entity(&controller, a));
+
+        world.insert_resource(controller);
+        world.run_system_once(cull).unwrap();
+        assert_eq!(world.get::<Visibility>(far), Some(&Visibility::Inherited));
+        assert_eq!(world.get::<Visibility>(near), Some(&Visibility::Inherited));
+
+        // Wall in C closes the only way to target
+        world.resource_mut::<Controller>().visibility.insert(c, ChunkVisibility([0; 6]));
+        world.run_system_once(cull).unwrap();
+        assert_eq!(world.get::<Visibility>(far), Some(&Visibility::Hidden));
+        assert_eq!(world.get::<Visibility>(near), Some(&Visibility::Inherited));
+    }
+}
@@ -0,0 +1,378 @@
+//! WorldEdit-like region operations
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+use bevy::{
+    prelude::*,
+    utils::HashMap,
+    tasks::*,
+};
+use bevy_egui::{egui, EguiContexts};
+use super::*;
+
+/// Regions bigger than this are computed on compute pool
+pub const ASYNC_VOLUME: usize = 32 * 32 * 32;
+/// Max chunk batches written per frame
+pub const BATCHES_PER_FRAME: usize = 8;
+
+/// Inclusive box of blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub min: IVec3,
+    pub max: IVec3
+}
+
+impl Region {
+    pub fn new(a: IVec3, b: IVec3) -> Self {
+        Self { min: a.min(b), max: a.max(b) }
+    }
+
+    pub fn size(&self) -> IVec3 {
+        self.max - self.min + IVec3::ONE
+    }
+
+    pub fn volume(&self) -> usize {
+        // Big selection overflows i32
+        self.size().as_i64vec3().element_product() as usize
+    }
+
+    /// Block on any of 6 region sides
+    pub fn is_outline(&self, pos: IVec3) -> bool {
+        pos.cmpeq(self.min).any() || pos.cmpeq(self.max).any()
+    }
+
+    /// Block on 4 vertical region sides
+    pub fn is_wall(&self, pos: IVec3) -> bool {
+        pos.x == self.min.x || pos.x == self.max.x || pos.z == self.min.z || pos.z == self.max.z
+    }
+
+    /// All chunks intersecting region
+    pub fn chunks(&self) -> Vec<IVec3> {
+        let (min, _) = RawChunk::split(self.min);
+        let (max, _) = RawChunk::split(self.max);
+
+        let mut result = Vec::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    result.push(IVec3::new(x, y, z));
+                }
+            }
+        }
+        result
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionOp {
+    /// Fill whole region with block
+    Fill(u16),
+    /// Replace first block with second
+    Replace(u16, u16),
+    /// Remove blocks not exposed to air
+    Hollow,
+    /// Fill 4 vertical sides
+    Walls(u16),
+    /// Fill all 6 sides
+    Outline(u16)
+}
+
+/// Block changes grouped by chunk: chunk pos, (block index, computed from block, new block)
+pub type Batches = Vec<(IVec3, Vec<(usize, u16, u16)>)>;
+
+impl RegionOp {
+    fn block(chunks: &HashMap<IVec3, Vec<u16>>, pos: IVec3) -> u16 {
+        let (chunk, local) = RawChunk::split(pos);
+        chunks.get(&chunk).map(|c| c[RawChunk::block_index(local)]).unwrap_or(0)
+    }
+
+    /// New block for pos or None if unchanged
+    fn result(&self, region: &Region, pos: IVec3, current: u16, get: impl Fn(IVec3) -> u16) -> Option<u16> {
+        let block = match *self {
+            Self::Fill(b) => b,
+            Self::Replace(from, to) => if current == from { to } else { current },
+            Self::Walls(b) => if region.is_wall(pos) { b } else { current },
+            Self::Outline(b) => if region.is_outline(pos) { b } else { current },
+            Self::Hollow => {
+                let inner = current != 0 && [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z]
+                    .iter().all(|o| get(pos + *o) != 0);
+
+                if inner { 0 } else { current }
+            }
+        };
+
+        (block != current).then_some(block)
+    }
+
+    /// Compute all region changes over loaded chunks
+    pub fn compute(self, region: Region, chunks: HashMap<IVec3, Chunk>, progress: Arc<AtomicUsize>) -> Batches {
+        // Copy blocks out, so chunks aren't locked while computing
+        let chunks: HashMap<_, _> = chunks.iter().map(|(pos, c)| (*pos, c.read().get().clone())).collect();
+
+        let mut batches = Vec::new();
+        for (pos, blocks) in chunks.iter() {
+            let origin = *pos * RawChunk::SIZE_I32;
+            let min = (region.min - origin).max(IVec3::ZERO);
+            let max = (region.max - origin).min(IVec3::splat(RawChunk::SIZE_I32 - 1));
+            if min.cmpgt(max).any() { continue; }
+
+            let get = |p: IVec3| Self::block(&chunks, p);
+
+            let mut data = Vec::new();
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    for x in min.x..=max.x {
+                        let local = IVec3::new(x, y, z);
+                        let index = RawChunk::block_index(local);
+                        let current = blocks[index];
+                        if let Some(block) = self.result(&region, origin + local, current, get) {
+                            data.push((index, current, block));
+                        }
+                    }
+                }
+
+                let row = ((max.x - min.x + 1) * (max.z - min.z + 1)) as usize;
+                progress.fetch_add(row, Ordering::Relaxed);
+            }
+
+            if !data.is_empty() {
+                batches.push((*pos, data));
+            }
+        }
+
+        batches
+    }
+}
+
+/// Running region operation
+pub struct EditTask {
+    task: Task<Batches>,
+    progress: Arc<AtomicUsize>,
+    total: usize
+}
+
+#[derive(Resource, Default)]
+/// Region selection and current operations
+pub struct Editor {
+    pub first: Option<IVec3>,
+    pub second: Option<IVec3>,
+    /// Primary and secondary (replace from) blocks
+    pub block: u16,
+    pub from: u16,
+
+    task: Option<EditTask>,
+    /// Computed batches waiting to be written
+    pending: Batches
+}
+
+impl Editor {
+    pub fn region(&self) -> Option<Region> {
+        Some(Region::new(self.first?, self.second?))
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.task.is_some() || !self.pending.is_empty()
+    }
+
+    /// Operation progress (0..1) if running
+    pub fn progress(&self) -> Option<f32> {
+        if let Some(task) = &self.task {
+            let done = task.progress.load(Ordering::Relaxed);
+            return Some(done as f32 / task.total.max(1) as f32);
+        }
+
+        (!self.pending.is_empty()).then_some(1.0)
+    }
+
+    /// Start region operation
+    pub fn run(&mut self, op: RegionOp, controller: &Controller) {
+        let Some(region) = self.region() else { return };
+        if self.is_busy() { return; }
+
+        let chunks: HashMap<_, _> = region.chunks().into_iter()
+            .filter_map(|pos| Some((pos, controller.chunks.get(&pos).cloned()?)))
+            .collect();
+        let progress = Arc::new(AtomicUsize::new(0));
+        let total = region.volume();
+
+        if total < ASYNC_VOLUME {
+            self.pending = op.compute(region, chunks, progress);
+        } else {
+            let p = progress.clone();
+            let task = ComputeTaskPool::get().spawn(async move { op.compute(region, chunks, p) });
+            self.task = Some(EditTask { task, progress, total });
+        }
+    }
+}
+
+/// Set region corners from selected block
+pub fn select(
+    kbd: Res<ButtonInput<KeyCode>>,
+    selected: Res<systems::SelectedData>,
+    mut editor: ResMut<Editor>
+) {
+    let Some(hit) = selected.current else { return };
+
+    if kbd.just_pressed(KeyCode::BracketLeft) {
+        editor.first = Some(hit.block);
+    }
+    if kbd.just_pressed(KeyCode::BracketRight) {
+        editor.second = Some(hit.block);
+    }
+}
+
+/// Block choose combo box
+pub fn block_combo(ui: &mut egui::Ui, label: &str, names: &[String], block: &mut u16) {
+    let selected = names.get(*block as usize).cloned().unwrap_or_default();
+    egui::ComboBox::from_label(label).selected_text(selected).show_ui(ui, |ui| {
+        for (id, name) in names.iter().enumerate() {
+            ui.selectable_value(block, id as u16, name);
+        }
+    });
+}
+
+/// Region operations window
+pub fn ui(
+    mut contexts: EguiContexts,
+    mut editor: ResMut<Editor>,
+    controller: Res<Controller>,
+    world: Res<WorldRes>
+) {
+    let names = world.blocks.names();
+
+    egui::Window::new("Region").default_open(false).show(contexts.ctx_mut(), |ui| {
+        ui.label(format!("First: {:?}; Second: {:?}", editor.first, editor.second));
+        if let Some(region) = editor.region() {
+            ui.label(format!("Size: {}; Volume: {}", region.size(), region.volume()));
+        }
+
+        let mut block = editor.block;
+        let mut from = editor.from;
+        block_combo(ui, "Block", &names, &mut block);
+        block_combo(ui, "Replace from", &names, &mut from);
+        (editor.block, editor.from) = (block, from);
+
+        let mut op = None;
+        ui.horizontal(|ui| {
+            if ui.button("Fill").clicked() { op = Some(RegionOp::Fill(block)); }
+            if ui.button("Replace").clicked() { op = Some(RegionOp::Replace(from, block)); }
+            if ui.button("Hollow").clicked() { op = Some(RegionOp::Hollow); }
+            if ui.button("Walls").clicked() { op = Some(RegionOp::Walls(block)); }
+            if ui.button("Outline").clicked() { op = Some(RegionOp::Outline(block)); }
+        });
+
+        if let Some(op) = op {
+            editor.run(op, &controller);
+        }
+
+        if let Some(progress) = editor.progress() {
+            ui.add(egui::ProgressBar::new(progress).show_percentage());
+        }
+    });
+}
+
+/// Join computed operation and write batches through controller.
+/// Blocks changed since computing are skipped, so newer edits aren't overwritten
+pub fn apply(
+    mut editor: ResMut<Editor>,
+    mut controller: ResMut<Controller>
+) {
+    if editor.task.as_ref().is_some_and(|t| t.task.is_finished()) {
+        let task = editor.task.take().unwrap();
+        editor.pending = block_on(task.task);
+    }
+
+    let n = BATCHES_PER_FRAME.min(editor.pending.len());
+    for (pos, data) in editor.pending.drain(..n) {
+        let Some(chunk) = controller.chunks.get(&pos).cloned() else { continue };
+        let guard = chunk.read();
+        let data: Vec<_> = data.into_iter()
+            .filter(|(index, from, _)| guard.get()[*index] == *from)
+            .map(|(index, _, to)| (index, to))
+            .collect();
+        drop(guard);
+
+        if !data.is_empty() {
+            controller.write_chunk(pos, &data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+    use super::*;
+
+    /// Changed blocks of op over empty chunk
+    fn changes(op: RegionOp, region: Region) -> Vec<IVec3> {
+        let chunks = HashMap::from_iter([(IVec3::ZERO, Chunk::new(RawChunk::empty()))]);
+        let batches = op.compute(region, chunks, Arc::default());
+        batches.into_iter().flat_map(|(_, data)| data).map(|(index, _, _)| RawChunk::block_pos(index)).collect()
+    }
+
+    #[test]
+    fn volume() {
+        let region = Region::new(IVec3::splat(-2000), IVec3::splat(2000));
+        assert_eq!(region.volume(), 4001usize.pow(3));
+    }
+
+    #[test]
+    fn fill_walls_outline() {
+        let region = Region::new(IVec3::ZERO, IVec3::splat(2));
+        assert_eq!(changes(RegionOp::Fill(5), region).len(), 27);
+
+        // Inner column stays
+        let walls = changes(RegionOp::Walls(5), region);
+        assert_eq!(walls.len(), 24);
+        assert!(walls.iter().all(|pos| pos.xz() != IVec2::ONE));
+
+        // Only center block stays
+        let outline = changes(RegionOp::Outline(5), region);
+        assert_eq!(outline.len(), 26);
+        assert!(!outline.contains(&IVec3::ONE));
+    }
+
+    #[test]
+    fn compute_pairs() {
+        let chunk = Chunk::new(RawChunk::filled(1));
+        let chunks = HashMap::from_iter([(IVec3::ZERO, chunk.clone())]);
+        let region = Region::new(IVec3::ZERO, IVec3::splat(2));
+
+        // Blocks on the chunk side touch unloaded (empty) chunk
+        let batches = RegionOp::Hollow.compute(region, chunks, Arc::default());
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].1.len(), 8);
+        assert!(batches[0].1.iter().all(|(index, from, to)| RawChunk::block_pos(*index).min_element() > 0 && (*from, *to) == (1, 0)));
+
+        // Compute doesn't hold locks after it returns
+        let center = RawChunk::block_index(IVec3::ONE);
+        chunk.write().get_mut()[center] = 2;
+        let batches = RegionOp::Replace(2, 3).compute(region, HashMap::from_iter([(IVec3::ZERO, chunk)]), Arc::default());
+        assert_eq!(batches, vec![(IVec3::ZERO, vec![(center, 2, 3)])]);
+    }
+
+    #[test]
+    fn apply_skips_changed() {
+        let chunk = Chunk::new(RawChunk::filled(1));
+        let mut controller = Controller::default();
+        controller.chunks.insert(IVec3::ZERO, chunk.clone());
+        let region = Region::new(IVec3::ZERO, IVec3::ONE);
+        let pending = RegionOp::Fill(2).compute(region, HashMap::from_iter([(IVec3::ZERO, chunk.clone())]), Arc::default());
+
+        // Block is edited after compute, before batches are written
+        let changed = RawChunk::block_index(IVec3::ONE);
+        chunk.write().get_mut()[changed] = 3;
+
+        let mut world = World::new();
+        world.insert_resource(controller);
+        world.insert_resource(Editor { pending, ..default() });
+        world.run_system_once(apply).unwrap();
+
+        let guard = chunk.read();
+        assert_eq!(guard.get()[changed], 3);
+        assert_eq!(guard.get().iter().filter(|b| **b == 2).count(), 7);
+        assert!(!world.resource::<Editor>().is_busy());
+    }
+}
@@ -0,0 +1,141 @@
+//! Hotbar and block palette
+
+use bevy::{
+    prelude::*,
+    input::mouse::MouseWheel,
+};
+use bevy_egui::{egui, EguiContexts};
+use super::*;
+
+pub const SLOTS: usize = 9;
+const ICON_SIZE: f32 = 32.0;
+
+const KEYS: [KeyCode; SLOTS] = [
+    KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3,
+    KeyCode::Digit4, KeyCode::Digit5, KeyCode::Digit6,
+    KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+];
+
+#[derive(Resource)]
+pub struct Hotbar {
+    pub slots: [u16; SLOTS],
+    pub active: usize,
+    /// Egui block icons by block id
+    icons: Vec<Option<egui::TextureId>>
+}
+
+impl Hotbar {
+    /// Fill slots with first non-empty blocks
+    pub fn new(blocks: &BlocksHandler) -> Self {
+        let mut slots = [0; SLOTS];
+        let all = blocks.all().into_iter().filter(|b| blocks.is_meshable(*b));
+        for (slot, block) in slots.iter_mut().zip(all) {
+            *slot = block;
+        }
+
+        Self { slots, active: 0, icons: Vec::new() }
+    }
+
+    /// Current block
+    pub fn selected(&self) -> u16 {
+        self.slots[self.active]
+    }
+
+    pub fn scroll(&mut self, delta: i32) {
+        self.active = (self.active as i32 - delta).rem_euclid(SLOTS as i32) as usize;
+    }
+
+    /// Select block slot if exists, else put it into active slot
+    pub fn pick(&mut self, block: u16) {
+        match self.slots.iter().position(|b| *b == block) {
+            Some(slot) => self.active = slot,
+            None => self.slots[self.active] = block
+        }
+    }
+}
+
+/// Hotbar selection by keys, scroll and pick block
+pub fn update(
+    mut hotbar: ResMut<Hotbar>,
+    mut scroll: EventReader<MouseWheel>,
+    kbd: Res<ButtonInput<KeyCode>>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    selected: Res<systems::SelectedData>,
+    brush: Res<brush::Brush>
+) {
+    if let Some(slot) = KEYS.iter().position(|k| kbd.just_pressed(*k)) {
+        hotbar.active = slot;
+    }
+
+    let delta: f32 = scroll.read().map(|e| e.y).sum();
+    if kbd.pressed(SCROLL_MODIFIER) && !brush.enabled && delta != 0.0 {
+        hotbar.scroll(delta.signum() as i32);
+    }
+
+    if mouse_buttons.just_pressed(MouseButton::Middle) {
+        if let Some(hit) = selected.current {
+            hotbar.pick(hit.data);
+        }
+    }
+}
+
+/// Hotbar panel and blocks palette
+pub fn ui(
+    mut contexts: EguiContexts,
+    mut hotbar: ResMut<Hotbar>,
+    world: Res<WorldRes>
+) {
+    // Reloaded blocks have other icons and may be fewer
+    let blocks = world.blocks.all();
+    if world.is_changed() {
+        hotbar.icons.clear();
+        let last = blocks.len().saturating_sub(1) as u16;
+        hotbar.slots.iter_mut().for_each(|slot| *slot = (*slot).min(last));
+    }
+
+    // Register block icons once
+    hotbar.icons.resize(blocks.len(), None);
+    for block in blocks {
+        if hotbar.icons[block as usize].is_some() { continue; }
+        hotbar.icons[block as usize] = world.blocks.icon(block).map(|t| contexts.add_image(t));
+    }
+
+    let names = world.blocks.names();
+    let icon = |block: u16| hotbar.icons.get(block as usize).copied().flatten();
+    let image = |id: egui::TextureId| egui::Image::new(egui::load::SizedTexture::new(id, [ICON_SIZE; 2]));
+
+    let mut active = hotbar.active;
+    let mut picked = None;
+
+    egui::TopBottomPanel::bottom("hotbar").show(contexts.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            for (slot, block) in hotbar.slots.iter().enumerate() {
+                let name = names.get(*block as usize).cloned().unwrap_or_default();
+                let response = match icon(*block) {
+                    Some(id) => ui.add(egui::ImageButton::new(image(id)).selected(slot == active)),
+                    None => ui.selectable_label(slot == active, name.clone())
+                };
+
+                if response.on_hover_text(name).clicked() { active = slot; }
+            }
+        });
+    });
+
+    egui::Window::new("Blocks").default_open(false).show(contexts.ctx_mut(), |ui| {
+        ui.horizontal_wrapped(|ui| {
+            for block in world.blocks.all() {
+                let Some(id) = icon(block) else { continue };
+                let name = names.get(block as usize).cloned().unwrap_or_default();
+                if ui.add(egui::ImageButton::new(image(id))).on_hover_text(name).clicked() {
+                    picked = Some(block);
+                }
+            }
+        });
+    });
+
+    hotbar.active = active;
+    if let Some(block) = picked {
+        let slot = hotbar.active;
+        hotbar.slots[slot] = block;
+    }
+}
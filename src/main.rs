@@ -5,14 +5,23 @@ mod systems;
 mod camera;
 mod debug;
 mod world;
+mod schematic;
+mod edit;
+mod brush;
+mod hotbar;
+mod culling;
+mod scheduler;
 
-use ordermap::OrderSet;
+use ordermap::{OrderMap, OrderSet};
+use strum::IntoEnumIterator;
 use bevy::{
     prelude::*,
     utils::*,
     tasks::*,
     asset::io::*,
     image::*,
+    math::Affine3A,
+    render::primitives::{Aabb, Frustum},
 };
 
 use camera::*;
@@ -21,6 +30,7 @@ use chunk::*;
 use rendering::*;
 use debug::*;
 use world::*;
+use culling::*;
 
 // Todo:
 // 1) World load-store system
@@ -32,59 +42,118 @@ use world::*;
 /// Main stored world chunks data
 pub struct Controller {
     pub chunks: HashMap<IVec3, chunk::Chunk>,
-    pub meshes: HashMap<IVec3, Entity>,
-    /// load chunks queue; build meshes queue
+    /// Chunk sections mesh entities, reused by rebuilds
+    pub meshes: HashMap<IVec3, [SectionEntities; SECTIONS]>,
+    /// load chunks queue; build meshes queue with sections bits
     pub load: OrderSet<IVec3>,
-    pub build: OrderSet<IVec3>,
+    pub build: OrderMap<IVec3, u8>,
 
     /// unload and despawn queue
-    pub _unload: Vec<IVec3>,
+    pub unload: Vec<IVec3>,
     pub despawn: Vec<Entity>,
 
     /// Compute tasks
-    pub load_tasks: HashMap<IVec3, Task<RawChunk>>,
-    pub build_tasks: HashMap<IVec3, Task<Option<Mesh>>>,
-    pub need_sort: bool
+    pub load_tasks: HashMap<IVec3, ChunkTask<RawChunk>>,
+    pub build_tasks: HashMap<IVec3, ChunkTask<(ChunkVisibility, Vec<ChunkMeshes>)>>,
+    /// Chunk data and mesh generations, so older tasks results are dropped.
+    /// Mesh is changed by requeue, both are removed on unload
+    pub generations: HashMap<IVec3, u32>,
+    pub mesh_generations: HashMap<IVec3, u32>,
+    pub need_sort: bool,
+
+    /// Level of detail of built chunks meshes
+    pub lods: HashMap<IVec3, u8>,
+    /// Camera chunk for LOD selection
+    pub camera: IVec3,
+    /// Full detail meshes culling
+    pub strategy: MeshStrategy,
+    /// Connected faces of built chunks for cave culling
+    pub visibility: HashMap<IVec3, ChunkVisibility>,
+    /// Hide chunks unreachable from camera chunk
+    pub culling: bool,
+
+    /// Lifecycle state of every known chunk
+    pub states: HashMap<IVec3, ChunkState>,
+    /// State changes, sent as events by systems::chunk_events
+    pub transitions: Vec<ChunkStateChanged>
 }
 
-impl Default for Controller {
-    fn default() -> Self {
-        let n = 8;
-        let k = n-1;
-
-        // Test generate chunks area
-        let mut load: OrderSet<IVec3> = OrderSet::new();
-        for x in -n..n {
-            for y in -n..n {
-                for z in -n..n {
-                    load.insert(IVec3::new(x, y, z));
-                }
-            }
-        }
+/// Chunk lifecycle: Queued -> Generating -> Decorating -> Generated -> Meshing -> Ready, rebuilds go
+/// Ready -> Meshing -> Ready. Dropped tasks go back to Queued, Generated or Ready.
+/// Chunks out of load radius go Unloading and end with no state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChunkState {
+    /// In load queue
+    Queued,
+    /// Generate task is running
+    Generating,
+    /// Has terrain data, decoration pass runs (it's empty yet)
+    Decorating,
+    /// Has blocks data, mesh is queued or waits for neighbours data
+    Generated,
+    /// Mesh task is running
+    Meshing,
+    /// Mesh is joined, rebuild may be queued
+    Ready,
+    /// In unload queue
+    Unloading
+}
 
-        let mut build = OrderSet::new();
-        for x in -k..k {
-            for y in -k..k {
-                for z in -k..k {
-                    build.insert(IVec3::new(x, y, z));
-                }
-            }
-        }
+/// Chunk state transition, none state is unknown (not queued or unloaded) chunk
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkStateChanged {
+    pub chunk: IVec3,
+    pub from: Option<ChunkState>,
+    pub to: Option<ChunkState>
+}
+
+/// Background task of chunk, dropping it cancels task
+pub struct ChunkTask<T> {
+    /// Chunk generation when task was spawned
+    pub generation: u32,
+    /// Mesh sections bits, requeued if task is cancelled or outdated
+    pub sections: u8,
+    pub task: Task<T>
+}
+
+/// Section mesh entity of every pass in ChunkMeshes::PASSES order
+pub type SectionEntities = [Option<Entity>; 3];
+
+/// Priority multiplier of chunks out of view (up to + 2 behind camera)
+pub const OUT_OF_VIEW_PRIORITY: f32 = 2.0;
 
+/// Chunks distance of every next level of detail, all levels fit into load radius
+pub const LOD_DISTANCE: i32 = LOAD_RADIUS / (MAX_LOD as i32 + 1);
+
+/// Chunks distance from camera chunk which are loaded, meshes are built one chunk closer
+pub const LOAD_RADIUS: i32 = 8;
+
+impl Default for Controller {
+    fn default() -> Self {
         Self {
             chunks: HashMap::with_capacity(1024),
             meshes: HashMap::with_capacity(1024),
-            // Load-rebuild chunks 
-            //load: OrderSet::with_capacity(1024),
-            //build: OrderSet::with_capacity(1024),
-            load, build,
+            // Chunks around camera are queued by systems::update_radius
+            load: OrderSet::with_capacity(1024),
+            build: OrderMap::with_capacity(1024),
             
-            _unload: Vec::with_capacity(512),
+            unload: Vec::with_capacity(512),
             despawn: Vec::with_capacity(512),
 
             load_tasks: HashMap::new(),
             build_tasks: HashMap::new(),
-            need_sort: true
+            generations: HashMap::with_capacity(1024),
+            mesh_generations: HashMap::with_capacity(1024),
+            need_sort: true,
+
+            lods: HashMap::with_capacity(1024),
+            camera: IVec3::ZERO,
+            strategy: MeshStrategy::default(),
+            visibility: HashMap::with_capacity(1024),
+            culling: true,
+
+            states: HashMap::with_capacity(1024),
+            transitions: Vec::with_capacity(1024)
         }
     }
 }
@@ -101,21 +170,185 @@ impl Controller {
 
     /// Reload all meshes & sort
     pub fn reload(&mut self) {
-        self.build.extend(self.meshes.keys().copied());
+        let chunks: Vec<_> = self.meshes.keys().copied().collect();
+        for chunk in chunks {
+            self.queue(chunk, ALL_SECTIONS);
+        }
         self.sort();
     }
 
-    // Rebuild chunk meshes
-    pub fn rebuild(&mut self, chunk: IVec3) {
-        self.build.extend(ChunksRefs::offsets(chunk));
+    /// Add chunk sections to build queue, running build becomes outdated
+    pub fn queue(&mut self, chunk: IVec3, sections: u8) {
+        *self.build.entry(chunk).or_default() |= sections;
+        Self::bump(&mut self.mesh_generations, chunk);
+    }
+
+    /// Current chunk lifecycle state
+    pub fn state(&self, chunk: IVec3) -> Option<ChunkState> {
+        self.states.get(&chunk).copied()
+    }
+
+    /// Change chunk state, transition is recorded if state is other
+    pub fn set_state(&mut self, chunk: IVec3, state: Option<ChunkState>) {
+        let from = match state {
+            Some(state) => self.states.insert(chunk, state),
+            None => self.states.remove(&chunk)
+        };
+        if from != state {
+            self.transitions.push(ChunkStateChanged { chunk, from, to: state });
+        }
+    }
+
+    /// Remove chunk from load and build queues, it's unloaded on systems::unload
+    pub fn request_unload(&mut self, chunk: IVec3) {
+        self.load.remove(&chunk);
+        self.build.remove(&chunk);
+        self.set_state(chunk, Some(ChunkState::Unloading));
+        self.unload.push(chunk);
+    }
+
+    /// Current chunk data generation
+    pub fn generation(&self, chunk: IVec3) -> u32 {
+        self.generations.get(&chunk).copied().unwrap_or_default()
+    }
+
+    /// Current chunk mesh generation
+    pub fn mesh_generation(&self, chunk: IVec3) -> u32 {
+        self.mesh_generations.get(&chunk).copied().unwrap_or_default()
+    }
+
+    fn bump(generations: &mut HashMap<IVec3, u32>, chunk: IVec3) {
+        let generation = generations.entry(chunk).or_default();
+        *generation = generation.wrapping_add(1);
+    }
+
+    /// Cancel chunk tasks, remove it from queues and despawn its meshes
+    pub fn unload_chunk(&mut self, chunk: IVec3) {
+        self.generations.remove(&chunk);
+        self.mesh_generations.remove(&chunk);
+        self.load.remove(&chunk);
+        self.build.remove(&chunk);
+        self.load_tasks.remove(&chunk);
+        self.build_tasks.remove(&chunk);
+
+        self.chunks.remove(&chunk);
+        self.lods.remove(&chunk);
+        self.visibility.remove(&chunk);
+        if let Some(sections) = self.meshes.remove(&chunk) {
+            self.despawn.extend(sections.into_iter().flatten().flatten());
+        }
+        self.set_state(chunk, None);
+    }
+
+    /// Rebuild mesh sections which sample changed blocks (by block index), 
+    /// faces and AO read one block around, so border blocks touch loaded neighbours
+    pub fn rebuild(&mut self, chunk: IVec3, blocks: impl IntoIterator<Item = usize>) {
+        // Sections bits of chunk and neighbours in ChunksRefs::OFFSETS order
+        let mut sections = [0u8; 27];
+        for index in blocks {
+            let local = RawChunk::block_pos(index);
+            for offset in ChunksRefs::OFFSETS {
+                let (neighbour, local) = RawChunk::split(local + offset);
+                let i = (neighbour.x + 1) + (neighbour.y + 1) * 3 + (neighbour.z + 1) * 9;
+                sections[i as usize] |= 1 << (local.y / SECTION_HEIGHT);
+            }
+        }
+
+        for (offset, sections) in ChunksRefs::OFFSETS.into_iter().zip(sections) {
+            if sections != 0 && self.chunks.contains_key(&(chunk + offset)) {
+                self.queue(chunk + offset, sections);
+            }
+        }
         self.sort();
     }
 
+    /// Level of detail for chunk by distance to camera chunk
+    pub fn lod(&self, pos: IVec3) -> u8 {
+        let distance = (pos - self.camera).abs().max_element();
+        (distance / LOD_DISTANCE).min(MAX_LOD as i32) as u8
+    }
+
+    /// Sides (Direction::to_u32 bits) facing chunks with other level of detail
+    pub fn lod_borders(&self, pos: IVec3) -> u8 {
+        let lod = self.lod(pos);
+        Direction::iter()
+            .filter(|dir| self.lod(pos + dir.air_sample()) != lod)
+            .fold(0, |borders, dir| borders | 1 << dir.to_u32())
+    }
+
+    /// Load and build queues order (lower is sooner): distance in chunks,
+    /// chunks out of view are delayed more the further they are behind camera
+    pub fn priority(pos: IVec3, camera: &Transform, frustum: &Frustum) -> f32 {
+        let size = RawChunk::SIZE_F32;
+        let center = (pos.as_vec3() + 0.5) * size;
+        let offset = center - camera.translation;
+        let distance = offset.length() / size;
+
+        let bounds = Aabb::from_min_max(pos.as_vec3() * size, (pos + 1).as_vec3() * size);
+        if frustum.intersects_obb(&bounds, &Affine3A::IDENTITY, true, false) {
+            return distance;
+        }
+
+        let facing = camera.forward().dot(offset.normalize_or_zero());
+        distance * (OUT_OF_VIEW_PRIORITY + 1.0 - facing)
+    }
+
+    /// Get block by global pos if chunk is loaded
+    pub fn get_block(&self, pos: IVec3) -> Option<u16> {
+        let (chunk, local) = RawChunk::split(pos);
+        let chunk = self.chunks.get(&chunk)?;
+        let data = chunk.read().get()[RawChunk::block_index(local)];
+        Some(data)
+    }
+
+    /// Find first non-empty block on the ray (unloaded chunks are skipped)
+    pub fn raycast(&self, origin: Vec3, dir: Vec3, max_distance: f32) -> Option<RayHit> {
+        VoxelRay::new(origin, dir)
+            .take_while(|step| step.distance <= max_distance)
+            .find_map(|step| {
+                let data = self.get_block(step.voxel).filter(|b| *b != 0)?;
+                Some(RayHit { block: step.voxel, normal: step.normal, distance: step.distance, data })
+            })
+    }
+
+    /// Write blocks by global pos grouped by chunk, rebuild changed chunks.
+    /// Returns count of skipped blocks (chunk isn't loaded)
+    pub fn set_blocks(&mut self, blocks: impl IntoIterator<Item = (IVec3, u16)>) -> usize {
+        let mut grouped = HashMap::<IVec3, Vec<(usize, u16)>>::new();
+        for (pos, block) in blocks {
+            let (chunk, local) = RawChunk::split(pos);
+            grouped.entry(chunk).or_default().push((RawChunk::block_index(local), block));
+        }
+
+        let mut skipped = 0;
+        for (pos, data) in grouped {
+            if !self.write_chunk(pos, &data) {
+                skipped += data.len();
+            }
+        }
+
+        skipped
+    }
+
+    /// Write blocks batch (block index, block) into loaded chunk and rebuild it
+    pub fn write_chunk(&mut self, pos: IVec3, data: &[(usize, u16)]) -> bool {
+        let Some(chunk) = self.chunks.get(&pos).cloned() else { return false };
+
+        let mut guard = chunk.write();
+        for (index, block) in data {
+            guard.get_mut()[*index] = *block;
+        }
+        drop(guard);
+
+        self.rebuild(pos, data.iter().map(|(index, _)| *index));
+        true
+    }
+
     // Get chunk refs
     pub fn refs(&self, pos: IVec3) -> Option<ChunksRefs> {
-        let mut data = Vec::<Chunk>::with_capacity(7);
-        for n in 0..7 {
-            data.push(self.chunks.get(&(pos + ChunksRefs::OFFSETS[n])).cloned()?)
+        let mut data = Vec::<Chunk>::with_capacity(27);
+        for offset in ChunksRefs::OFFSETS {
+            data.push(self.chunks.get(&(pos + offset)).cloned()?)
         }
         Some(ChunksRefs::new(Self::to_array(data)))
     }
@@ -126,18 +359,23 @@ pub struct EnginePlugin;
 impl Plugin for EnginePlugin {
     fn build(&self, app: &mut App) {
         app.init_state::<MainState>()
+        .init_resource::<schematic::Clipboard>()
+        .init_resource::<edit::Editor>()
+        .init_resource::<brush::Brush>()
+        .init_resource::<scheduler::Scheduler>()
+        .add_event::<ChunkStateChanged>()
         .add_plugins(bevy_egui::EguiPlugin)
         .add_plugins((WorldPlugin, DebugPlugin, CameraPlugin, RenderingPlugin))
         .add_systems(Update,
-            (systems::keybind).run_if(in_state(MainState::InGame))
+            (systems::keybind, schematic::import, schematic::paste, edit::select, edit::ui, brush::update, brush::ui, hotbar::update, hotbar::ui, scheduler::ui).run_if(in_state(MainState::InGame))
         ).add_systems(FixedUpdate,
             (systems::skybox).run_if(in_state(MainState::InGame))
         ).add_systems(FixedPostUpdate,
             systems::update_selected.run_if(in_state(MainState::InGame))
         ).add_systems(PostUpdate,
-            (systems::hot_reload, systems::begin).chain().run_if(in_state(MainState::InGame))
+            (edit::apply, systems::hot_reload, systems::bake_models, systems::reload_textures, systems::update_lod, systems::update_radius, systems::begin).chain().run_if(in_state(MainState::InGame))
         ).add_systems(Last,
-            (systems::unload, systems::join).chain().run_if(in_state(MainState::InGame))
+            (systems::unload, systems::join, systems::sort_translucent, culling::cull, systems::chunk_events).chain().run_if(in_state(MainState::InGame))
         );
     }
 }
@@ -164,6 +402,8 @@ pub fn main() {
             .set(ImagePlugin { default_sampler: default_sampler() })
         ).init_asset::<WorldData>()
         .init_asset_loader::<WorldLoader>()
+        .init_asset::<CustomModel>()
+        .init_asset_loader::<CustomModelLoader>()
         .add_plugins(EnginePlugin)
         .run();
 }
\ No newline at end of file
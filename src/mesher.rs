@@ -1,13 +1,17 @@
 use super::*;
 use strum::IntoEnumIterator;
 use bevy::math::*;
+use std::ops::Range;
 use bevy::render::{
-    mesh::*,
-    render_asset::*
+    primitives::Aabb,
+    render_asset::*,
+    storage::ShaderStorageBuffer
 };
 
 // Also face normal
-#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::EnumIter)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::EnumIter)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Direction {
     Left, Right, Down, Up, Back, Forward
 }
@@ -37,6 +41,18 @@ impl Direction {
         }
     }
 
+    /// Direction with negated normal
+    pub fn opposite(&self) -> Self {
+        match self {
+            Self::Up => Self::Down,
+            Self::Down => Self::Up,
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+            Self::Forward => Self::Back,
+            Self::Back => Self::Forward,
+        }
+    }
+
     pub fn negate_axis(&self) -> i32 {
         match self {
             Self::Up => 1,
@@ -59,6 +75,15 @@ impl Direction {
         }
     }
     
+    /// Inverse of world_sample: (axis, row, column) of block position
+    pub fn grid_sample(&self, pos: IVec3) -> (i32, i32, i32) {
+        match self {
+            Self::Up | Self::Down => (pos.y, pos.x, pos.z),
+            Self::Left | Self::Right => (pos.x, pos.z, pos.y),
+            Self::Forward | Self::Back => (pos.z, pos.x, pos.y),
+        }
+    }
+
     pub fn to_u32(&self) -> u32 {
         match self {
             Self::Up => 0,
@@ -71,161 +96,754 @@ impl Direction {
     }
 }
 
-pub struct Face {x: i32, y: i32}
+/// Merged quad on slice grid
+pub struct Face {x: i32, y: i32, w: i32, h: i32}
 
 /// All blocks face methods
 impl Face {
-    pub fn new(x: i32, y: i32) -> Self {
-        Self { x, y }
-    }
-
-    /// UV corners
-    pub const UVS: [UVec2; 4] = [
-        UVec2::new(1, 1),
-        UVec2::new(0, 1),
-        UVec2::new(0, 0),
-        UVec2::new(1, 0)
-    ];
-
-    /// Make vertices from face
-    pub fn vertices(self, dir: Direction, mut axis: i32, block: u16) -> Vec<Vertex> {
-        axis += dir.negate_axis();
-        let v1 = Vertex::new(
-            dir.world_sample(axis, self.x, self.y), 
-            dir,
-            block as u32,
-            &Self::UVS[0]
-        );
-
-        let v2 = Vertex::new(
-            dir.world_sample(axis, self.x + 1, self.y), 
-            dir,
-            block as u32,
-            &Self::UVS[1]
-        );
-
-        let v3 = Vertex::new(
-            dir.world_sample(axis, self.x + 1, self.y + 1), 
-            dir,
-            block as u32,
-            &Self::UVS[2]
-        );
-
-        let v4 = Vertex::new(
-            dir.world_sample(axis, self.x, self.y + 1), 
-            dir,
-            block as u32,
-            &Self::UVS[3]
-        );
-        
-        let mut new = std::collections::VecDeque::from([v1, v2, v3, v4]);
-        if dir.reverse_order() {
-            let o = new.split_off(1);
-            o.into_iter().rev().for_each(|i| new.push_back(i));
-        }
+    pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+        Self { x, y, w, h }
+    }
 
-        Vec::from(new)
+    /// Packed quad of face, ao is corners occlusion.
+    /// Grid coords are multiplied by scale (downsampled LOD grid)
+    pub fn quad(self, dir: Direction, axis: i32, texture: u32, ao: [u32; 4], scale: i32) -> Quad {
+        let axis = (axis + dir.negate_axis()) * scale;
+        let (x, y, w, h) = (self.x * scale, self.y * scale, self.w * scale, self.h * scale);
+        debug_assert!(texture <= u16::MAX as u32, "Texture layer {} doesn't fit quad", texture);
+
+        let data = x as u32
+        | (y as u32) << 5u32
+        | (w as u32 - 1) << 10u32
+        | (h as u32 - 1) << 15u32
+        | (axis as u32) << 20u32
+        | dir.to_u32() << 26u32;
+        let ao = ao[0] | ao[1] << 2 | ao[2] << 4 | ao[3] << 6;
+
+        Quad(data, texture | ao << 16 | Vertex::FULL_LIGHT << 24)
     }
 }
 
-/// Pocket of vertex data
-/// [6]bits - X (0-63)
-/// [6]bits - Y (0-63)
-/// [6]bits - Z (0-63)
-/// [3]bits - Face (0-7)
-/// [7]bits - texture_x (0-255)
-/// [1]bit - UVx (0/1)
-/// [1]bit - UVy (0/1)
+/// Greedy meshed face, expanded into 4 vertices by shader (vertex pulling)
+///
+/// Data:
+/// [5]bits - Row, column (0-31) on face slice
+/// [5]bits - Width, height (1-32) minus one
+/// [6]bits - Slice (0-32)
+/// [3]bits - Face (0-5)
+///
+/// Extra:
+/// [16]bits - Texture layer (0-65535)
+/// [2]bits - AO (0-3) of 4 corners
+/// [4]bits - Light (0-15)
+///
+/// Corners (row, column), (row + w, column), (row + w, column + h), (row, column + h)
+/// are ordered by Direction::reverse_order and rotated when AO
+/// of second diagonal is bigger, so AO gradient isn't anisotropic
 #[derive(Debug, Clone, Copy)]
-pub struct Vertex(u32);
+pub struct Quad(u32, u32);
+
+impl Quad {
+    /// Local corners positions in blocks
+    pub fn corners(&self) -> [Vec3; 4] {
+        let dir = [Direction::Up, Direction::Left, Direction::Right, Direction::Forward, Direction::Back, Direction::Down]
+            [(self.0 >> 26 & 7) as usize];
+        let (x, y) = ((self.0 & 31) as i32, (self.0 >> 5 & 31) as i32);
+        let (w, h) = ((self.0 >> 10 & 31) as i32 + 1, (self.0 >> 15 & 31) as i32 + 1);
+        let axis = (self.0 >> 20 & 63) as i32;
+
+        [(x, y), (x + w, y), (x + w, y + h), (x, y + h)].map(|(r, c)| dir.world_sample(axis, r, c).as_vec3())
+    }
+}
+
+/// Pocket of models (crossed and custom) vertex data, two words
+///
+/// Data:
+/// [6]bits - X (0-32)
+/// [6]bits - Y (0-32)
+/// [6]bits - Z (0-32)
+/// [3]bits - Face (0-5; 6-7 - crossed model front/back)
+/// [4]bits - Light (0-15)
+/// [1]bit - Precise (custom models)
+/// [6]bits - Detail high bits
+///
+/// Extra:
+/// [16]bits - Texture layer (0-65535)
+/// [16]bits - Detail low bits
+///
+/// Detail (22 bits) of block faces:
+/// [2]bits - AO (0-3)
+/// [6]bits - U, V (0-32) in blocks, so texture tiles over merged faces
+///
+/// Detail of precise vertices (AO is always 3):
+/// [4]bits - X, Y, Z fraction (1/16)
+/// [5]bits - U, V (0-16) in 1/16 of block
+#[derive(Debug, Clone, Copy)]
+pub struct Vertex(u32, u32);
 
 impl Vertex {
-    pub fn new(local: IVec3, dir: Direction, block: u32, uv: &UVec2) -> Self {
+    /// Crossed model quads side
+    pub const CROSSED: u32 = 6;
+    /// Light level until there is lighting
+    pub const FULL_LIGHT: u32 = 15;
+
+    fn pack(local: IVec3, side: u32, texture: u32, precise: bool, detail: u32) -> Self {
+        debug_assert!(texture <= u16::MAX as u32, "Texture layer {} doesn't fit vertex", texture);
+
         let data = local.x as u32
         | (local.y as u32) << 6u32
         | (local.z as u32) << 12u32
-        | (dir.to_u32()) << 18u32
-        | (block) << 21u32 // Block id also texture id in binding array 
-        | (uv.x) << 28u32  // UV may be only 0 or 1
-        | (uv.y) << 29u32; 
-        
-        Self(data)
+        | side << 18u32
+        | Self::FULL_LIGHT << 21u32
+        | (precise as u32) << 25u32
+        | (detail >> 16) << 26u32;
+
+        Self(data, texture | (detail & 0xFFFF) << 16)
     }
-}   
 
-/// All mesh vertices
+    /// Block face vertex, uv in blocks
+    pub fn new(local: IVec3, side: u32, texture: u32, ao: u32, uv: UVec2) -> Self {
+        Self::pack(local, side, texture, false, ao | uv.x << 2 | uv.y << 8)
+    }
+
+    /// Vertex from position in 1/16 of block, uv in 1/16 of block
+    pub fn precise(local: IVec3, side: u32, texture: u32, uv: UVec2) -> Self {
+        let fraction = local.rem_euclid(IVec3::splat(16)).as_uvec3();
+        let uv = uv.min(UVec2::splat(16));
+        let detail = fraction.x | fraction.y << 4 | fraction.z << 8 | uv.x << 12 | uv.y << 17;
+
+        Self::pack(local.div_euclid(IVec3::splat(16)), side, texture, true, detail)
+    }
+
+    fn detail(&self) -> u32 {
+        (self.0 >> 26) << 16 | self.1 >> 16
+    }
+
+    /// Local position in blocks
+    pub fn position(&self) -> Vec3 {
+        let block = UVec3::new(self.0 & 63, self.0 >> 6 & 63, self.0 >> 12 & 63).as_vec3();
+        if self.0 >> 25 & 1 == 0 { return block; }
+
+        let detail = self.detail();
+        let fraction = UVec3::new(detail & 15, detail >> 4 & 15, detail >> 8 & 15).as_vec3();
+        block + fraction / 16.0
+    }
+
+    /// Texture coords of face point: -position projection (repeating) shifted by size
+    /// to stay positive, pos is in 1/size units
+    pub fn face_uv(pos: IVec3, side: u32, size: i32) -> UVec2 {
+        let uv = match side {
+            0 | 5 => pos.xz(), // Up, Down
+            1 | 2 => pos.zy(), // Left, Right
+            _ => pos.xy()      // Forward, Back, Crossed
+        };
+        (IVec2::splat(size) - uv).max(IVec2::ZERO).as_uvec2()
+    }
+}
+
+/// Section pass faces and models vertices (4 per quad)
 #[derive(Debug, Default, Clone)]
-#[repr(transparent)]
 pub struct ChunkMesh {
-    vertices: Vec<Vertex>
+    faces: Vec<Quad>,
+    models: Vec<Vertex>
 }
 
 impl ChunkMesh {
-    fn make_vertices(dir: Direction, handler: &BlocksHandler, refs: &ChunksRefs) -> Vec<Vertex> {
-        let mut vertices = Vec::with_capacity(512);
-        let size = RawChunk::SIZE_I32;
+    /// Ambient occlusion (0-3) of face corners in Quad corners order
+    fn face_ao(dir: Direction, pos: IVec3, handler: &BlocksHandler, get: &impl Fn(IVec3) -> u16) -> [u32; 4] {
+        let air = pos + dir.air_sample();
+        let (row, column) = (dir.world_sample(0, 1, 0), dir.world_sample(0, 0, 1));
+        let solid = |p: IVec3| handler.is_occluder(get(p)) as u32;
 
-        // Culled meshser
-        let n = dir.negate_axis();
-        for axis in n..(size+n) {
+        [(-1, -1), (1, -1), (1, 1), (-1, 1)].map(|(r, c)| {
+            let (side1, side2) = (solid(air + row * r), solid(air + column * c));
+            if side1 == 1 && side2 == 1 {
+                0
+            } else {
+                3 - side1 - side2 - solid(air + row * r + column * c)
+            }
+        })
+    }
+
+    /// Is face between current block and its neighbour visible
+    fn is_visible(current: u16, neighbour: u16, handler: &BlocksHandler) -> bool {
+        if !handler.is_meshable(current) || handler.is_occluder(neighbour) {
+            return false;
+        }
+        // Skip faces between same translucent blocks (glass next to glass)
+        !(handler.opacity(current) == Opacity::Translucent && current == neighbour)
+    }
+
+    /// Visible face of block at pos: block id with packed AO or 0
+    fn visible_face(dir: Direction, pos: IVec3, handler: &BlocksHandler, get: &impl Fn(IVec3) -> u16, scale: i32) -> u32 {
+        let (current, neg_z) = (get(pos), get(pos + dir.air_sample()));
+        if !Self::is_visible(current, neg_z, handler) { return 0; }
+
+        let ao = if scale == 1 { Self::face_ao(dir, pos, handler, get) } else { [3; 4] };
+        current as u32 | (ao[0] | ao[1] << 2 | ao[2] << 4 | ao[3] << 6) << 16
+    }
+
+    /// Culled and greedy meshed faces of size^3 grid blocks in ys layers into pass of block opacity.
+    /// Grid is chunk blocks or downsampled LOD grid (no AO) scaled back to blocks
+    fn make_vertices(
+        dir: Direction,
+        handler: &BlocksHandler,
+        get: &impl Fn(IVec3) -> u16,
+        (size, scale): (i32, i32),
+        ys: Range<i32>,
+        passes: &mut Passes
+    ) {
+        let mut mask = vec![0u32; size.pow(2) as usize];
+        for axis in 0..size {
             for i in 0..size.pow(2) {
-                let row = i % size;
-                let column = i / size;
+                let pos = dir.world_sample(axis, i % size, i / size);
+                mask[i as usize] = if ys.contains(&pos.y) {
+                    Self::visible_face(dir, pos, handler, get, scale)
+                } else { 0 };
+            }
+            Self::greedy(dir, axis, handler, &mut mask, (size, scale), passes);
+        }
+    }
+
+    /// Same faces as make_vertices, but culled with bitwise ops on padded chunk
+    /// columns (bit is block along face axis), only visible faces are sampled
+    fn make_vertices_binary(dir: Direction, handler: &BlocksHandler, chunk: &PaddedChunk, columns: &BinaryColumns, ys: Range<i32>, passes: &mut Passes) {
+        let size = RawChunk::SIZE_I32;
+        let padded = PaddedChunk::SIZE_I32;
+        let get = |pos: IVec3| chunk.get(pos);
+        // Center chunk blocks of column
+        let center = ((1u64 << size) - 1) << 1;
+
+        let mut masks = vec![vec![0u32; size.pow(2) as usize]; size as usize];
+        let (meshable, occluders) = columns.get(dir);
+        for i in 0..size.pow(2) {
+            let (row, column) = (i % size, i / size);
+            let index = (row + 1 + (column + 1) * padded) as usize;
+
+            // Neighbour of bit is next (positive direction) or previous bit
+            let neighbours = if dir.negate_axis() == 1 { occluders[index] >> 1 } else { occluders[index] << 1 };
+            let mut visible = meshable[index] & !neighbours & center;
+            while visible != 0 {
+                let axis = visible.trailing_zeros() as i32 - 1;
+                visible &= visible - 1;
+
                 let pos = dir.world_sample(axis, row, column);
-                let (current, neg_z) =
-                    (refs.get_block(pos), refs.get_block(pos + dir.air_sample()));
+                if ys.contains(&pos.y) {
+                    masks[axis as usize][i as usize] = Self::visible_face(dir, pos, handler, &get, 1);
+                }
+            }
+        }
+
+        for (axis, mask) in masks.iter_mut().enumerate() {
+            Self::greedy(dir, axis as i32, handler, mask, (size, 1), passes);
+        }
+    }
+
+    /// Greedy mesher: merge same faces (block and AO) of slice mask into rectangles
+    fn greedy(dir: Direction, axis: i32, handler: &BlocksHandler, mask: &mut [u32], (size, scale): (i32, i32), passes: &mut Passes) {
+        let index = |row: i32, column: i32| (row + column * size) as usize;
+
+        for column in 0..size {
+            let mut row = 0;
+            while row < size {
+                let face = mask[index(row, column)];
+                if face == 0 {
+                    row += 1;
+                    continue;
+                }
+
+                let mut w = 1;
+                while row + w < size && mask[index(row + w, column)] == face {
+                    w += 1;
+                }
 
-                if handler.is_meshable(current) && !handler.is_meshable(neg_z) {
-                    let face = Face::new(row, column);
-                    vertices.extend(face.vertices(dir, axis, current));
+                let mut h = 1;
+                while column + h < size && (0..w).all(|k| mask[index(row + k, column + h)] == face) {
+                    h += 1;
                 }
+
+                for c in column..column + h {
+                    mask[index(row, c)..index(row + w, c)].fill(0);
+                }
+
+                let (block, ao) = (face as u16, face >> 16);
+                let ao = [ao & 3, ao >> 2 & 3, ao >> 4 & 3, ao >> 6 & 3];
+                let face = Face::new(row, column, w, h).quad(dir, axis, handler.texture(block, dir), ao, scale);
+                passes.get(handler.opacity(block)).faces.push(face);
+                row += w;
+            }
+        }
+    }
+
+    /// Two diagonal quads (both sides) for every crossed block in ys layers
+    fn make_crossed(handler: &BlocksHandler, chunk: &PaddedChunk, ys: Range<i32>) -> Vec<Vertex> {
+        let mut vertices = Vec::new();
+
+        for (pos, block) in chunk.blocks(ys) {
+            if !handler.is_crossed(block) { continue; }
+
+            let planes = [
+                [IVec3::ZERO, IVec3::new(1, 0, 1), IVec3::ONE, IVec3::Y],
+                [IVec3::X, IVec3::Z, IVec3::new(0, 1, 1), IVec3::new(1, 1, 0)]
+            ];
+
+            let texture = handler.texture(block, Direction::Up);
+            for plane in planes {
+                let vertex = |c: IVec3, side: u32| Vertex::new(pos + c, side, texture, 3, Vertex::face_uv(c, side, 1));
+                let front = plane.map(|c| vertex(c, Vertex::CROSSED));
+                let back = plane.map(|c| vertex(c, Vertex::CROSSED + 1));
+                vertices.extend(front);
+                vertices.extend(back.into_iter().rev());
             }
         }
 
         vertices
     }
 
-    pub async fn build(handler: BlocksHandler, refs: ChunksRefs) -> Option<Mesh> {
-        let mut mesh = Self::default();
+    /// Custom model face quad
+    fn custom_face(pos: IVec3, element: &BakedElement, face: &BakedFace) -> Vec<Vertex> {
+        let dir = face.dir;
+        let (normal, row, column) = (dir.world_sample(1, 0, 0), dir.world_sample(0, 1, 0), dir.world_sample(0, 0, 1));
+        let bound = if dir.negate_axis() == 1 { element.to } else { element.from };
 
-        // Apply all directions
-        for dir in Direction::iter() {
-            mesh.vertices.extend(Self::make_vertices(dir, &handler, &refs));
-        }
-        
-        if !mesh.vertices.is_empty() {
-            Some(mesh.spawn())
-        } else {
-            None
-        }
-    }
-
-    pub fn generate_indices(&self) -> Vec<u32> {
-        let indices_count = self.vertices.len() / 4;
-        let mut indices = Vec::<u32>::with_capacity(indices_count);
-        (0..indices_count).into_iter().for_each(|vert_index| {
-            let vert_index = vert_index as u32 * 4u32;
-            indices.push(vert_index);
-            indices.push(vert_index + 1);
-            indices.push(vert_index + 2);
-            indices.push(vert_index);
-            indices.push(vert_index + 2);
-            indices.push(vert_index + 3);
+        let axis = bound.dot(normal);
+        let (r0, r1) = (element.from.dot(row), element.to.dot(row));
+        let (c0, c1) = (element.from.dot(column), element.to.dot(column));
+        let uv = face.uv.map(|[u0, v0, u1, v1]| [
+            UVec2::new(u1, v1), UVec2::new(u0, v1), UVec2::new(u0, v0), UVec2::new(u1, v0)
+        ]);
+
+        let corners = [(r0, c0), (r1, c0), (r1, c1), (r0, c1)];
+        let mut new: std::collections::VecDeque<_> = corners.into_iter().enumerate().map(|(i, (r, c))| {
+            let relative = dir.world_sample(axis, r, c);
+            let uv = uv.map(|uv| uv[i]).unwrap_or(Vertex::face_uv(relative, dir.to_u32(), 16));
+            Vertex::precise(pos * 16 + relative, dir.to_u32(), face.texture, uv)
+        }).collect();
+
+        if dir.reverse_order() {
+            let o = new.split_off(1);
+            o.into_iter().rev().for_each(|i| new.push_back(i));
+        }
+
+        Vec::from(new)
+    }
+
+    /// Custom models elements in ys layers, faces are culled by full neighbours
+    fn make_custom(handler: &BlocksHandler, chunk: &PaddedChunk, ys: Range<i32>) -> Vec<Vertex> {
+        let mut vertices = Vec::new();
+
+        for (pos, block) in chunk.blocks(ys) {
+            let Some(model) = handler.model(block) else { continue };
+
+            for element in model.elements.iter() {
+                for face in element.faces.iter() {
+                    let culled = face.cullface.is_some_and(|c| handler.is_occluder(chunk.get(pos + c.air_sample())));
+                    if !culled {
+                        vertices.extend(Self::custom_face(pos, element, face));
+                    }
+                }
+            }
+        }
+
+        vertices
+    }
+
+    /// Downsampled center chunk: cell is most common meshable block
+    /// if at least half of the cell is meshable. Returns grid size
+    fn downsample(handler: &BlocksHandler, chunk: &PaddedChunk, scale: i32) -> (Vec<u16>, i32) {
+        let size = RawChunk::SIZE_I32 / scale;
+
+        let mut grid = vec![0u16; size.pow(3) as usize];
+        let mut counts: Vec<(u16, i32)> = Vec::new();
+        for (i, cell) in grid.iter_mut().enumerate() {
+            let i = i as i32;
+            let min = IVec3::new(i % size, i / size.pow(2), (i / size) % size) * scale;
+
+            counts.clear();
+            for y in 0..scale {
+                for z in 0..scale {
+                    for x in 0..scale {
+                        let b = chunk.get(min + IVec3::new(x, y, z));
+                        if !handler.is_meshable(b) { continue; }
+                        match counts.iter_mut().find(|(k, _)| *k == b) {
+                            Some((_, n)) => *n += 1,
+                            None => counts.push((b, 1))
+                        }
+                    }
+                }
+            }
+
+            let solid: i32 = counts.iter().map(|(_, n)| n).sum();
+            if solid * 2 >= scale.pow(3) {
+                *cell = counts.iter().max_by_key(|(_, n)| *n).map(|(b, _)| *b).unwrap_or(0);
+            }
+        }
+
+        (grid, size)
+    }
+
+    /// Build meshes of chunk sections (bit per section) at level of detail (grid downsampled by 2^lod).
+    /// LOD meshes have only block faces, border faces aren't culled,
+    /// they are too coarse for sections: whole mesh is first section, others are empty.
+    /// Full detail border faces aren't culled on `borders` sides (Direction::to_u32 bits) facing
+    /// other LOD chunks, so different surfaces don't leave cracks between chunks.
+    /// Strategy selects full detail faces culling. Chunk faces visibility is computed with meshes
+    pub async fn build(handler: BlocksHandler, refs: ChunksRefs, lod: u8, borders: u8, strategy: MeshStrategy, sections: u8) -> (ChunkVisibility, Vec<ChunkMeshes>) {
+        // Chunks are locked only here
+        let mut chunk = PaddedChunk::new(&refs);
+        drop(refs);
+        let visibility = ChunkVisibility::new(&handler, &chunk);
+
+        if lod > 0 {
+            let mut passes = Passes::default();
+            let scale = 1 << lod.min(MAX_LOD);
+            let (grid, size) = Self::downsample(&handler, &chunk, scale);
+            let get = |pos: IVec3| {
+                if pos.cmplt(IVec3::ZERO).any() || pos.cmpge(IVec3::splat(size)).any() { return 0; }
+                grid[(pos.x + pos.z * size + pos.y * size * size) as usize]
+            };
+            for dir in Direction::iter() {
+                Self::make_vertices(dir, &handler, &get, (size, scale), 0..size, &mut passes);
+            }
+
+            let mut meshes = vec![passes.finish(lod, 0)];
+            meshes.extend((1..SECTIONS).map(|section| Passes::default().finish(lod, section)));
+            return (visibility, meshes);
+        }
+
+        for dir in Direction::iter().filter(|dir| borders >> dir.to_u32() & 1 == 1) {
+            chunk.clear_side(dir.air_sample());
+        }
+
+        let columns = (strategy == MeshStrategy::Binary).then(|| BinaryColumns::new(&handler, &chunk));
+        let meshes = (0..SECTIONS).filter(|section| sections >> section & 1 == 1).map(|section| {
+            let mut passes = Passes::default();
+            let ys = ChunkMeshes::section_layers(section);
+
+            // Apply all directions
+            for dir in Direction::iter() {
+                match &columns {
+                    None => {
+                        let get = |pos: IVec3| chunk.get(pos);
+                        Self::make_vertices(dir, &handler, &get, (RawChunk::SIZE_I32, 1), ys.clone(), &mut passes);
+                    }
+                    Some(columns) => Self::make_vertices_binary(dir, &handler, &chunk, columns, ys.clone(), &mut passes)
+                }
+            }
+            // Models may have transparent pixels
+            passes.cutout.models.extend(Self::make_crossed(&handler, &chunk, ys.clone()));
+            passes.cutout.models.extend(Self::make_custom(&handler, &chunk, ys));
+
+            passes.finish(lod, section)
+        }).collect();
+
+        (visibility, meshes)
+    }
+
+    /// Quads count (faces and models)
+    pub fn quads(&self) -> u32 {
+        (self.faces.len() + self.models.len() / 4) as u32
+    }
+
+    /// Local bounds of vertices, none if mesh is empty
+    pub fn aabb(&self) -> Option<Aabb> {
+        let faces = self.faces.iter().flat_map(|q| q.corners());
+        Aabb::enclosing(faces.chain(self.models.iter().map(|v| v.position())))
+    }
+
+    /// Storage buffer of chunk origin, faces count, faces and models vertices
+    pub fn buffer(&self, origin: Vec3, usage: RenderAssetUsages) -> ShaderStorageBuffer {
+        let mut data = Vec::with_capacity(16 + (self.faces.len() + self.models.len()) * 8);
+        for word in origin.to_array().map(f32::to_bits).into_iter().chain([self.faces.len() as u32]) {
+            data.extend(word.to_le_bytes());
+        }
+
+        let faces = self.faces.iter().map(|q| (q.0, q.1));
+        for (a, b) in faces.chain(self.models.iter().map(|v| (v.0, v.1))) {
+            data.extend(a.to_le_bytes());
+            data.extend(b.to_le_bytes());
+        }
+
+        ShaderStorageBuffer::new(&data, usage)
+    }
+}
+
+/// Full detail faces culling of ChunkMesh::build
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MeshStrategy {
+    /// Block and neighbour lookup for every face
+    #[default]
+    Scalar,
+    /// Bitwise culling of u64 columns
+    Binary
+}
+
+impl MeshStrategy {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Scalar => Self::Binary,
+            Self::Binary => Self::Scalar
+        }
+    }
+}
+
+/// Padded chunk masks of meshable and occluding blocks, column per axis.
+/// Column bits are padded coords along axis, columns index is row + column * 34
+struct BinaryColumns {
+    /// X, Y, Z axis columns
+    meshable: [Vec<u64>; 3],
+    occluders: [Vec<u64>; 3]
+}
+
+impl BinaryColumns {
+    /// Direction which grid sample is used for axis columns
+    const AXES: [Direction; 3] = [Direction::Right, Direction::Up, Direction::Back];
+
+    fn new(handler: &BlocksHandler, chunk: &PaddedChunk) -> Self {
+        let padded = PaddedChunk::SIZE_I32;
+        let empty = || vec![0u64; PaddedChunk::SIZE.pow(2)];
+        let mut meshable = [empty(), empty(), empty()];
+        let mut occluders = [empty(), empty(), empty()];
+
+        for i in 0..padded.pow(3) {
+            let pos = IVec3::new(i % padded, i / padded.pow(2), (i / padded) % padded);
+            let block = chunk.get(pos - IVec3::ONE);
+            let (is_meshable, is_occluder) = (handler.is_meshable(block), handler.is_occluder(block));
+            if !is_meshable && !is_occluder { continue; }
+
+            for (axis, dir) in Self::AXES.iter().enumerate() {
+                let (bit, row, column) = dir.grid_sample(pos);
+                let index = (row + column * padded) as usize;
+                meshable[axis][index] |= (is_meshable as u64) << bit;
+                occluders[axis][index] |= (is_occluder as u64) << bit;
+            }
+        }
+
+        Self { meshable, occluders }
+    }
+
+    /// Meshable and occluders columns of direction axis
+    fn get(&self, dir: Direction) -> (&[u64], &[u64]) {
+        let axis = match dir {
+            Direction::Left | Direction::Right => 0,
+            Direction::Down | Direction::Up => 1,
+            Direction::Forward | Direction::Back => 2
+        };
+        (&self.meshable[axis], &self.occluders[axis])
+    }
+}
+
+/// Vertices of every render pass
+#[derive(Default)]
+struct Passes {
+    opaque: ChunkMesh,
+    cutout: ChunkMesh,
+    translucent: ChunkMesh
+}
+
+impl Passes {
+    fn get(&mut self, opacity: Opacity) -> &mut ChunkMesh {
+        match opacity {
+            Opacity::Opaque => &mut self.opaque,
+            Opacity::Cutout => &mut self.cutout,
+            Opacity::Translucent => &mut self.translucent
+        }
+    }
+
+    /// Meshes with bounds of non-empty passes
+    fn finish(self, lod: u8, section: usize) -> ChunkMeshes {
+        let Self { opaque, cutout, translucent } = self;
+        // Translucent pass has only block faces, they are re-sorted by camera
+        let quads = (!translucent.faces.is_empty()).then(|| TranslucentQuads::new(&translucent));
+        let mesh = |pass: ChunkMesh| pass.aabb().map(|aabb| (pass, aabb));
+
+        ChunkMeshes {
+            lod,
+            section,
+            passes: [mesh(opaque), mesh(cutout), mesh(translucent)],
+            quads
+        }
+    }
+}
+
+/// Max level of detail (grid downsampled by 8)
+pub const MAX_LOD: u8 = 3;
+
+/// Height of chunk mesh section (32x8x32 layers), edits rebuild only touched sections
+pub const SECTION_HEIGHT: i32 = 8;
+/// Sections count of one chunk
+pub const SECTIONS: usize = (RawChunk::SIZE_I32 / SECTION_HEIGHT) as usize;
+/// Bits of all chunk sections
+pub const ALL_SECTIONS: u8 = (1 << SECTIONS) - 1;
+
+/// Chunk section meshes for opaque, alpha masked and blended passes
+pub struct ChunkMeshes {
+    pub lod: u8,
+    /// Section index (layers from section * SECTION_HEIGHT)
+    pub section: usize,
+    /// Meshes with local bounds in PASSES order
+    pub passes: [Option<(ChunkMesh, Aabb)>; 3],
+    /// Translucent quads for sorting
+    pub quads: Option<TranslucentQuads>
+}
+
+impl ChunkMeshes {
+    pub const PASSES: [Opacity; 3] = [Opacity::Opaque, Opacity::Cutout, Opacity::Translucent];
+
+    /// Chunk block layers of section
+    pub fn section_layers(section: usize) -> Range<i32> {
+        let min = section as i32 * SECTION_HEIGHT;
+        min..min + SECTION_HEIGHT
+    }
+
+}
+
+/// Translucent faces with centers in local coords, used for back-to-front sorting
+#[derive(Component, Debug, Clone)]
+pub struct TranslucentQuads(Vec<(Vec3, Quad)>);
+
+impl TranslucentQuads {
+    pub fn new(mesh: &ChunkMesh) -> Self {
+        Self(mesh.faces.iter().map(|quad| {
+            (quad.corners().into_iter().sum::<Vec3>() / 4.0, *quad)
+        }).collect())
+    }
+
+    /// Faces sorted from farthest to nearest to eye (local coords)
+    pub fn sorted(&self, eye: Vec3) -> ChunkMesh {
+        let mut order: Vec<_> = self.0.iter().collect();
+        order.sort_by(|(a, _), (b, _)| b.distance_squared(eye).total_cmp(&a.distance_squared(eye)));
+
+        ChunkMesh { faces: order.into_iter().map(|(_, quad)| *quad).collect(), models: Vec::new() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use bevy::tasks::block_on;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+    use super::*;
+
+    fn refs(center: RawChunk, mut neighbours: impl FnMut() -> RawChunk) -> ChunksRefs {
+        let mut center = Some(center);
+        let chunks = ChunksRefs::OFFSETS.map(|offset| match offset == IVec3::ZERO {
+            true => Chunk::new(center.take().unwrap()),
+            false => Chunk::new(neighbours())
         });
-        indices
+        ChunksRefs::new(chunks)
     }
 
-    pub fn spawn(self) -> Mesh {
-        let mut mesh = Mesh::new(
-            PrimitiveTopology::TriangleList,
-            RenderAssetUsages::RENDER_WORLD,
-        );
+    fn faces(meshes: &[ChunkMeshes]) -> Vec<Quad> {
+        meshes.iter().flat_map(|m| m.passes.iter().flatten()).flat_map(|(mesh, _)| mesh.faces.clone()).collect()
+    }
 
-        let indices = self.generate_indices();
-        let data: Vec<_> = self.vertices.into_iter().map(|v| v.0).collect();
-        mesh.insert_attribute(ATTRIBUTE_DATA, data);
-        mesh.insert_indices(Indices::U32(indices));
+    /// Faces of scalar meshing in all directions over whole chunk
+    fn scalar_faces(handler: &BlocksHandler, get: &impl Fn(IVec3) -> u16) -> Vec<(u32, u32)> {
+        let mut passes = Passes::default();
+        for dir in Direction::iter() {
+            ChunkMesh::make_vertices(dir, handler, get, (RawChunk::SIZE_I32, 1), 0..RawChunk::SIZE_I32, &mut passes);
+        }
+        faces(&[passes.finish(0, 0)]).iter().map(|q| (q.0, q.1)).collect()
+    }
+
+    /// Chunks where block is air or random of blocks with chance
+    fn random(rng: &mut StdRng, blocks: &[u16], chance: f64) -> RawChunk {
+        let mut chunk = RawChunk::empty();
+        for block in chunk.get_mut().iter_mut() {
+            if rng.gen_bool(chance) {
+                *block = blocks[rng.gen_range(0..blocks.len())];
+            }
+        }
+        chunk
+    }
+
+    #[test]
+    fn strategies_match() {
+        // Air, two opaque, cutout and two translucent blocks
+        let handler = BlocksHandler::test(&[Opacity::Opaque, Opacity::Opaque, Opacity::Cutout, Opacity::Translucent, Opacity::Translucent]);
+        let cases: [(&str, &[u16], f64); 6] = [
+            ("empty", &[1], 0.0),
+            ("full", &[1], 1.0),
+            ("sparse", &[1, 2, 3, 4, 5], 0.05),
+            ("half", &[1, 2, 3, 4, 5], 0.5),
+            ("dense", &[1, 2, 3, 4, 5], 0.95),
+            ("translucent pairs", &[4, 5], 0.7),
+        ];
+
+        let mut rng = StdRng::seed_from_u64(42);
+        for (name, blocks, chance) in cases {
+            for _ in 0..2 {
+                let center = random(&mut rng, blocks, chance);
+                let chunks: Vec<_> = (0..26).map(|_| random(&mut rng, blocks, chance)).collect();
+                let build = |strategy| {
+                    let mut neighbours = chunks.iter().cloned();
+                    let refs = refs(center.clone(), || neighbours.next().unwrap());
+                    block_on(ChunkMesh::build(handler.clone(), refs, 0, 0, strategy, ALL_SECTIONS)).1
+                };
+
+                let (scalar, binary) = (build(MeshStrategy::Scalar), build(MeshStrategy::Binary));
+                assert_eq!(scalar.len(), binary.len());
+                for (a, b) in scalar.iter().zip(binary.iter()) {
+                    for pass in 0..3 {
+                        let quads = |m: &ChunkMeshes| m.passes[pass].as_ref().map(|(mesh, _)| mesh.faces.iter().map(|q| (q.0, q.1)).collect::<Vec<_>>());
+                        assert_eq!(quads(a), quads(b), "{} section {} pass {}", name, a.section, pass);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lod_borders() {
+        let handler = BlocksHandler::test(&[Opacity::Opaque]);
+        let build = |borders: u8, strategy| {
+            let refs = refs(RawChunk::filled(1), || RawChunk::filled(1));
+            block_on(ChunkMesh::build(handler.clone(), refs, 0, borders, strategy, ALL_SECTIONS)).1
+        };
+
+        for strategy in [MeshStrategy::Scalar, MeshStrategy::Binary] {
+            assert!(faces(&build(0, strategy)).is_empty());
+
+            // One merged face per section on the right side
+            let right = 1 << Direction::Right.to_u32();
+            let quads = faces(&build(right, strategy));
+            assert_eq!(quads.len(), SECTIONS);
+            assert!(quads.iter().all(|q| q.corners().iter().all(|c| c.x == RawChunk::SIZE_F32)));
+        }
+    }
+
+    /// Meshing with per-voxel lookups (find chunk and lock it for every sample) against PaddedChunk copy.
+    /// Run with `cargo test --release -- --ignored --nocapture meshing_time`
+    #[test]
+    #[ignore]
+    fn meshing_time() {
+        let handler = BlocksHandler::test(&[Opacity::Opaque, Opacity::Opaque, Opacity::Cutout, Opacity::Translucent]);
+        let mut rng = StdRng::seed_from_u64(41);
+        let chunks = ChunksRefs::OFFSETS.map(|_| Chunk::new(random(&mut rng, &[1, 2, 3, 4], 0.5)));
+        let locked_get = |pos: IVec3| {
+            let (chunk, local) = RawChunk::split(pos);
+            let index = ChunksRefs::OFFSETS.iter().position(|o| *o == chunk).unwrap();
+            chunks[index].read().get()[RawChunk::block_index(local)]
+        };
+        let runs = 20;
+
+        let start = Instant::now();
+        let mut expected = Vec::new();
+        for _ in 0..runs {
+            expected = scalar_faces(&handler, &locked_get);
+        }
+        let locked = start.elapsed() / runs;
+
+        let start = Instant::now();
+        let mut result = Vec::new();
+        for _ in 0..runs {
+            let padded = PaddedChunk::new(&ChunksRefs::new(chunks.clone()));
+            result = scalar_faces(&handler, &|pos| padded.get(pos));
+        }
+        let padded = start.elapsed() / runs;
 
-        mesh
+        assert_eq!(result, expected);
+        println!("ChunksRefs per voxel: {:?}, PaddedChunk with copy: {:?} ({:.1}x)", locked, padded, locked.as_secs_f64() / padded.as_secs_f64());
     }
 }
@@ -1,149 +1,437 @@
-use std::num::NonZero;
-
+use std::sync::Arc;
 use bevy::{
     prelude::*,
     pbr::*,
+    core_pipeline::{
+        core_3d::*,
+        prepass::*,
+        tonemapping::{DebandDither, Tonemapping},
+    },
+    ecs::system::{lifetimeless::*, SystemParamItem},
     render::{
+        extract_component::*,
         mesh::*,
-        texture::*,
-        render_resource::{
-            binding_types::{sampler, texture_2d_array},
-            *,
-        },
         render_asset::*,
+        render_phase::*,
+        render_resource::{*, binding_types::*},
+        renderer::RenderDevice,
+        storage::*,
+        texture::GpuImage,
+        view::*,
+        Extract, Render, RenderApp, RenderSet,
     }
 };
 
-use super::BlocksHandler;
+use super::{Opacity, WorldRes};
 
-pub const ATTRIBUTE_DATA: MeshVertexAttribute =
-    MeshVertexAttribute::new("data", 536618, VertexFormat::Uint32);
+/// Section pass quads, pulled by chunk.wgsl from buffer made by ChunkMesh::buffer
+#[derive(Component, Clone, ExtractComponent)]
+pub struct ChunkQuads {
+    pub buffer: Handle<ShaderStorageBuffer>,
+    /// Faces and models quads count
+    pub quads: u32,
+    pub opacity: Opacity,
+    /// World center for translucent sections sorting
+    pub center: Vec3,
+}
 
-//todo: make custom bind group
-#[derive(Clone, Asset, Reflect, Debug)]
-pub struct ChunkMaterial {
-    textures: Vec<Option<Handle<Image>>>,
+/// Index buffer shared by all sections: 0 1 2 0 2 3 of every quad
+#[derive(Resource, Default)]
+struct ChunkIndices {
+    buffer: Option<Buffer>,
+    quads: u32
 }
 
-/// Set max textures bind group lenght
-pub const MAX_TEXTURES: usize = 256;
-
-impl AsBindGroup for ChunkMaterial {
-    type Data = ();
-    type Param = (Res<'static, RenderAssets<GpuImage>>, Res<'static, FallbackImage>);
-
-    fn as_bind_group(
-        &self,
-        layout: &BindGroupLayout,
-        render_device: &bevy::render::renderer::RenderDevice,
-        param: &mut bevy::ecs::system::SystemParamItem<'_, '_, Self::Param>,
-    ) -> Result<PreparedBindGroup<Self::Data>, AsBindGroupError> {
-        let mut images = vec![];
-
-        for handle_opt in self.textures.iter() {
-            let Some(handle) = handle_opt else {
-                images.push(None);
-                continue;
-            };
-
-            match param.0.get(handle) {
-                Some(image) => {
-                    // Retry later if only one layer
-                    if image.texture.depth_or_array_layers() == 1 {
-                        return Err(AsBindGroupError::RetryNextUpdate);
-                    }
-
-                    images.push(Some(image))
-                },
-                None => return Err(AsBindGroupError::RetryNextUpdate),
-            }
+/// Blocks texture array of current world
+#[derive(Resource, Default)]
+struct ChunkTextures {
+    image: Option<AssetId<Image>>,
+    /// Bind group with image it was created for
+    bind_group: Option<(AssetId<Image>, BindGroup)>
+}
+
+/// Section buffer bind group, recreated when buffer is uploaded again
+#[derive(Component)]
+struct SectionBindGroup(BufferId, BindGroup);
+
+#[derive(Resource)]
+struct ChunkPipeline {
+    mesh_pipeline: MeshPipeline,
+    textures_layout: BindGroupLayout,
+    section_layout: BindGroupLayout,
+    shader: Handle<Shader>,
+    /// Mesh pipeline is specialized by vertex layout, chunks vertices are pulled from buffer
+    layout: MeshVertexBufferLayoutRef
+}
+
+impl FromWorld for ChunkPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+        let textures_layout = device.create_bind_group_layout(
+            "chunk_textures_layout",
+            &BindGroupLayoutEntries::sequential(ShaderStages::FRAGMENT, (
+                texture_2d_array(TextureSampleType::Float { filterable: true }),
+                sampler(SamplerBindingType::Filtering),
+            ))
+        );
+        let section_layout = device.create_bind_group_layout(
+            "chunk_section_layout",
+            &BindGroupLayoutEntries::single(ShaderStages::VERTEX, storage_buffer_read_only_sized(false, None))
+        );
+
+        let layout = MeshVertexBufferLayout::new(vec![Mesh::ATTRIBUTE_POSITION.id], VertexBufferLayout {
+            array_stride: 12,
+            step_mode: VertexStepMode::Vertex,
+            attributes: vec![VertexAttribute { format: VertexFormat::Float32x3, offset: 0, shader_location: 0 }]
+        });
+
+        Self {
+            mesh_pipeline: world.resource::<MeshPipeline>().clone(),
+            textures_layout,
+            section_layout,
+            shader: world.resource::<AssetServer>().load("chunk.wgsl"),
+            layout: MeshVertexBufferLayoutRef(Arc::new(layout))
         }
-        
-        let fallback_image = &param.1.d2_array;
-        let textures = vec![&fallback_image.texture_view; MAX_TEXTURES];
-        let mut textures: Vec<_> = textures.into_iter().map(|texture| &**texture).collect();
-        for (id, image_opt) in images.into_iter().enumerate() {
-            if let Some(image) = image_opt {
-                textures[id] = &*image.texture_view;
+    }
+}
+
+impl SpecializedRenderPipeline for ChunkPipeline {
+    type Key = MeshPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let mut descriptor = self.mesh_pipeline.specialize(key, &self.layout)
+            .expect("Chunk pipeline specialization");
+
+        descriptor.label = Some("chunk_pipeline".into());
+        descriptor.layout = vec![descriptor.layout[0].clone(), self.textures_layout.clone(), self.section_layout.clone()];
+        descriptor.vertex.shader = self.shader.clone();
+        descriptor.vertex.buffers = Vec::new();
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            fragment.shader = self.shader.clone();
+            if key.contains(MeshPipelineKey::MAY_DISCARD) {
+                fragment.shader_defs.push("CUTOUT".into());
             }
         }
 
-        let bind_group = render_device.create_bind_group(
-            "chunk_bind_group",
-            layout,
-            &BindGroupEntries::sequential((&textures[..], &fallback_image.sampler)),
-        );
+        descriptor
+    }
+}
+
+type DrawChunk = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetChunkTexturesBindGroup<1>,
+    SetSectionBindGroup<2>,
+    DrawSection
+);
 
-        Ok(PreparedBindGroup {
-            bindings: vec![],
-            bind_group,
-            data: (),
-        })
+struct SetChunkTexturesBindGroup<const I: usize>;
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetChunkTexturesBindGroup<I> {
+    type Param = SRes<ChunkTextures>;
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        _entity: Option<()>,
+        textures: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some((_, bind_group)) = textures.into_inner().bind_group.as_ref() else { return RenderCommandResult::Skip };
+        pass.set_bind_group(I, bind_group, &[]);
+        RenderCommandResult::Success
     }
+}
 
-    fn unprepared_bind_group(
-        &self,
-        _layout: &BindGroupLayout,
-        _render_device: &bevy::render::renderer::RenderDevice,
-        _param: &mut bevy::ecs::system::SystemParamItem<'_, '_, Self::Param>,
-    ) -> Result<UnpreparedBindGroup<Self::Data>, AsBindGroupError> {
-        Ok(UnpreparedBindGroup {
-            bindings: vec![],
-            data: ()
-        })
+struct SetSectionBindGroup<const I: usize>;
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetSectionBindGroup<I> {
+    type Param = ();
+    type ViewQuery = ();
+    type ItemQuery = Read<SectionBindGroup>;
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        bind_group: Option<&'w SectionBindGroup>,
+        _param: (),
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(bind_group) = bind_group else { return RenderCommandResult::Skip };
+        pass.set_bind_group(I, &bind_group.1, &[]);
+        RenderCommandResult::Success
     }
+}
+
+/// Draw 6 shared indices of every section quad
+struct DrawSection;
+impl<P: PhaseItem> RenderCommand<P> for DrawSection {
+    type Param = SRes<ChunkIndices>;
+    type ViewQuery = ();
+    type ItemQuery = Read<ChunkQuads>;
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        quads: Option<&'w ChunkQuads>,
+        indices: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let indices = indices.into_inner();
+        let (Some(quads), Some(buffer)) = (quads, indices.buffer.as_ref()) else { return RenderCommandResult::Skip };
+        if quads.quads > indices.quads { return RenderCommandResult::Skip; }
 
-    fn bind_group_layout_entries(_render_device: &bevy::render::renderer::RenderDevice) -> Vec<BindGroupLayoutEntry>
-        where Self: Sized {
-            BindGroupLayoutEntries::with_indices(
-            ShaderStages::FRAGMENT,
-            (
-                (0, texture_2d_array(TextureSampleType::Float { filterable: true })
-                        .count(NonZero::<u32>::new(MAX_TEXTURES as u32).unwrap())),
-                (1, sampler(SamplerBindingType::Filtering)),
-            ),
-        )
-        .to_vec()
+        pass.set_index_buffer(buffer.slice(..), 0, IndexFormat::Uint32);
+        pass.draw_indexed(0..quads.quads * 6, 0, 0..1);
+        RenderCommandResult::Success
     }
 }
 
-/// Default chunk mesh
-impl ChunkMaterial {
-    pub fn new(handler: &BlocksHandler) -> Self {
-        Self { textures: handler.textures() }
+fn extract_textures(
+    world: Extract<Option<Res<WorldRes>>>,
+    mut textures: ResMut<ChunkTextures>
+) {
+    textures.image = world.as_ref().map(|w| w.textures.id());
+}
+
+/// Grow shared index buffer to fit the biggest section
+fn prepare_indices(
+    device: Res<RenderDevice>,
+    mut indices: ResMut<ChunkIndices>,
+    sections: Query<&ChunkQuads>
+) {
+    let needed = sections.iter().map(|s| s.quads).max().unwrap_or(0);
+    if needed <= indices.quads { return; }
+
+    let quads = needed.next_power_of_two();
+    let mut data = Vec::with_capacity(quads as usize * 6 * 4);
+    for quad in 0..quads {
+        for i in [0, 1, 2, 0, 2, 3] {
+            data.extend((quad * 4 + i).to_le_bytes());
+        }
     }
+
+    indices.buffer = Some(device.create_buffer_with_data(&BufferInitDescriptor {
+        label: Some("chunk_indices"),
+        contents: &data,
+        usage: BufferUsages::INDEX
+    }));
+    indices.quads = quads;
 }
 
-impl Material for ChunkMaterial {
-    fn vertex_shader() -> ShaderRef {
-        "chunk.wgsl".into()
+fn prepare_bind_groups(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    pipeline: Res<ChunkPipeline>,
+    images: Res<RenderAssets<GpuImage>>,
+    buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+    mut textures: ResMut<ChunkTextures>,
+    sections: Query<(Entity, &ChunkQuads, Option<&SectionBindGroup>)>
+) {
+    // Recreated only when world texture array is changed (or uploaded)
+    if textures.bind_group.as_ref().map(|(id, _)| *id) != textures.image {
+        textures.bind_group = textures.image.and_then(|id| Some((id, images.get(id)?))).map(|(id, image)| {
+            (id, device.create_bind_group(
+                "chunk_textures",
+                &pipeline.textures_layout,
+                &BindGroupEntries::sequential((&image.texture_view, &image.sampler))
+            ))
+        });
     }
-    fn fragment_shader() -> ShaderRef {
-        "chunk.wgsl".into()
+
+    for (entity, quads, current) in sections.iter() {
+        let Some(buffer) = buffers.get(&quads.buffer) else { continue };
+        if current.is_some_and(|c| c.0 == buffer.buffer.id()) { continue; }
+
+        let bind_group = device.create_bind_group(
+            "chunk_section",
+            &pipeline.section_layout,
+            &BindGroupEntries::single(buffer.buffer.as_entire_binding())
+        );
+        commands.entity(entity).insert(SectionBindGroup(buffer.buffer.id(), bind_group));
     }
+}
+
+/// Queue visible sections into opaque, alpha mask and transparent phases
+#[allow(clippy::type_complexity)]
+fn queue_chunks(
+    draw_functions: (Res<DrawFunctions<Opaque3d>>, Res<DrawFunctions<AlphaMask3d>>, Res<DrawFunctions<Transparent3d>>),
+    pipeline: Res<ChunkPipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<ChunkPipeline>>,
+    cache: Res<PipelineCache>,
+    sections: Query<&ChunkQuads>,
+    mut phases: (
+        ResMut<ViewBinnedRenderPhases<Opaque3d>>,
+        ResMut<ViewBinnedRenderPhases<AlphaMask3d>>,
+        ResMut<ViewSortedRenderPhases<Transparent3d>>
+    ),
+    views: Query<(
+        Entity,
+        &ExtractedView,
+        &RenderVisibleEntities,
+        &Msaa,
+        Option<&Tonemapping>,
+        Option<&DebandDither>,
+        Option<&ShadowFilteringMethod>,
+        Option<&Projection>,
+        (Has<NormalPrepass>, Has<DepthPrepass>, Has<MotionVectorPrepass>, Has<DeferredPrepass>),
+    )>
+) {
+    let draw_opaque = draw_functions.0.read().id::<DrawChunk>();
+    let draw_alpha_mask = draw_functions.1.read().id::<DrawChunk>();
+    let draw_transparent = draw_functions.2.read().id::<DrawChunk>();
+
+    for (
+        view_entity, view, visible, msaa, tonemapping, dither, shadow_filter, projection,
+        (normal_prepass, depth_prepass, motion_vector_prepass, deferred_prepass)
+    ) in views.iter() {
+        let (Some(opaque), Some(alpha_mask), Some(transparent)) = (
+            phases.0.get_mut(&view_entity),
+            phases.1.get_mut(&view_entity),
+            phases.2.get_mut(&view_entity)
+        ) else { continue };
+
+        // Same view key as materials get, so view bind group layout matches
+        let mut view_key = MeshPipelineKey::from_msaa_samples(msaa.samples())
+            | MeshPipelineKey::from_hdr(view.hdr)
+            | MeshPipelineKey::from_primitive_topology(PrimitiveTopology::TriangleList);
+
+        for (enabled, flag) in [
+            (normal_prepass, MeshPipelineKey::NORMAL_PREPASS),
+            (depth_prepass, MeshPipelineKey::DEPTH_PREPASS),
+            (motion_vector_prepass, MeshPipelineKey::MOTION_VECTOR_PREPASS),
+            (deferred_prepass, MeshPipelineKey::DEFERRED_PREPASS),
+        ] {
+            if enabled { view_key |= flag; }
+        }
+
+        view_key |= match projection {
+            Some(Projection::Orthographic(_)) => MeshPipelineKey::VIEW_PROJECTION_ORTHOGRAPHIC,
+            _ => MeshPipelineKey::VIEW_PROJECTION_PERSPECTIVE,
+        };
+        view_key |= match shadow_filter.copied().unwrap_or_default() {
+            ShadowFilteringMethod::Hardware2x2 => MeshPipelineKey::SHADOW_FILTER_METHOD_HARDWARE_2X2,
+            ShadowFilteringMethod::Gaussian => MeshPipelineKey::SHADOW_FILTER_METHOD_GAUSSIAN,
+            ShadowFilteringMethod::Temporal => MeshPipelineKey::SHADOW_FILTER_METHOD_TEMPORAL,
+        };
+
+        if !view.hdr {
+            if let Some(tonemapping) = tonemapping {
+                view_key |= MeshPipelineKey::TONEMAP_IN_SHADER | tonemapping_pipeline_key(*tonemapping);
+            }
+            if let Some(DebandDither::Enabled) = dither {
+                view_key |= MeshPipelineKey::DEBAND_DITHER;
+            }
+        }
+
+        let rangefinder = view.rangefinder3d();
+        for (render_entity, main_entity) in visible.iter::<With<ChunkQuads>>() {
+            let Ok(section) = sections.get(*render_entity) else { continue };
+            let entity = (*render_entity, *main_entity);
 
-    fn alpha_mode(&self) -> AlphaMode {
-        // Block face can't be transparent
-        AlphaMode::Opaque
+            match section.opacity {
+                Opacity::Opaque => {
+                    let pipeline = pipelines.specialize(&cache, &pipeline, view_key | MeshPipelineKey::BLEND_OPAQUE);
+                    opaque.add(Opaque3dBinKey {
+                        pipeline,
+                        draw_function: draw_opaque,
+                        asset_id: section.buffer.id().untyped(),
+                        material_bind_group_id: None,
+                        lightmap_image: None
+                    }, entity, BinnedRenderPhaseType::NonMesh);
+                }
+                Opacity::Cutout => {
+                    let pipeline = pipelines.specialize(&cache, &pipeline, view_key | MeshPipelineKey::MAY_DISCARD);
+                    alpha_mask.add(OpaqueNoLightmap3dBinKey {
+                        pipeline,
+                        draw_function: draw_alpha_mask,
+                        asset_id: section.buffer.id().untyped(),
+                        material_bind_group_id: None
+                    }, entity, BinnedRenderPhaseType::NonMesh);
+                }
+                Opacity::Translucent => {
+                    let pipeline = pipelines.specialize(&cache, &pipeline, view_key | MeshPipelineKey::BLEND_ALPHA);
+                    transparent.add(Transparent3d {
+                        entity,
+                        pipeline,
+                        draw_function: draw_transparent,
+                        distance: rangefinder.distance_translation(&section.center),
+                        batch_range: 0..1,
+                        extra_index: PhaseItemExtraIndex::NONE
+                    });
+                }
+            }
+        }
     }
+}
+
+/// Color of missing or not loaded textures
+const MISSING_COLOR: [u8; 4] = [255, 0, 255, 255];
+
+/// Pack block textures into one texture array, every texture is resized
+/// (nearest) to the biggest texture resolution. Layer is index in textures
+pub fn texture_array(images: &Assets<Image>, textures: &[Handle<Image>]) -> Image {
+    let loaded: Vec<_> = textures.iter().map(|t| images.get(t)).collect();
+    let size = loaded.iter().flatten()
+        .map(|i| i.size())
+        .fold(UVec2::ONE, |a, b| a.max(b));
+
+    let layers = textures.len().max(1) as u32;
+    let mut data = Vec::with_capacity((size.x * size.y * layers * 4) as usize);
+    for layer in 0..layers as usize {
+        let image = loaded.get(layer).copied().flatten();
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let pixel = image.and_then(|i| {
+                    let source = UVec2::new(x, y) * i.size() / size;
+                    i.get_color_at(source.x, source.y).ok()
+                }).map(|c| c.to_srgba().to_u8_array());
 
-    fn specialize(
-        _pipeline: &MaterialPipeline<Self>,
-        descriptor: &mut RenderPipelineDescriptor,
-        layout: &MeshVertexBufferLayoutRef,
-        _key: MaterialPipelineKey<Self>,
-    ) -> Result<(), SpecializedMeshPipelineError> {
-        let vertex_layout = layout.0.get_layout(&[
-            ATTRIBUTE_DATA.at_shader_location(0),
-        ])?;
-        descriptor.vertex.buffers = vec![vertex_layout];
-        Ok(())
+                data.extend(pixel.unwrap_or(MISSING_COLOR));
+            }
+        }
     }
+
+    let mut array = Image::new(
+        Extent3d { width: size.x, height: size.y, depth_or_array_layers: layers },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD
+    );
+    // Single layer is still an array
+    array.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        ..default()
+    });
+    array
 }
 
+/// Chunks sections renderer: vertices are pulled from section storage buffer
+/// by vertex index, all sections share one index buffer
 pub struct RenderingPlugin;
 impl Plugin for RenderingPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(MaterialPlugin::<ChunkMaterial>::default());
+        app.add_plugins(ExtractComponentPlugin::<ChunkQuads>::default())
+            .add_systems(PostUpdate, check_visibility::<With<ChunkQuads>>.in_set(VisibilitySystems::CheckVisibility));
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else { return };
+        render_app
+            .init_resource::<SpecializedRenderPipelines<ChunkPipeline>>()
+            .init_resource::<ChunkIndices>()
+            .init_resource::<ChunkTextures>()
+            .add_render_command::<Opaque3d, DrawChunk>()
+            .add_render_command::<AlphaMask3d, DrawChunk>()
+            .add_render_command::<Transparent3d, DrawChunk>()
+            .add_systems(ExtractSchedule, extract_textures)
+            .add_systems(Render, (
+                queue_chunks.in_set(RenderSet::QueueMeshes),
+                (prepare_indices, prepare_bind_groups).in_set(RenderSet::PrepareBindGroups),
+            ));
     }
-}   
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else { return };
+        render_app.init_resource::<ChunkPipeline>();
+    }
+}
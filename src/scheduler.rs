@@ -0,0 +1,89 @@
+//! Background generate and mesh tasks budget
+
+use bevy::{
+    prelude::*,
+    utils::Duration,
+};
+use bevy_egui::{egui, EguiContexts};
+
+/// Live counters, updated by begin and join
+#[derive(Debug, Default, Clone)]
+pub struct SchedulerStats {
+    /// Compute pool threads
+    pub threads: usize,
+    /// In-flight tasks limits
+    pub max_chunks: usize,
+    pub max_meshes: usize,
+    /// In-flight tasks
+    pub load_tasks: usize,
+    pub build_tasks: usize,
+    /// Results joined on last frame
+    pub joined_chunks: usize,
+    pub joined_meshes: usize,
+    /// Finished results left for next frame
+    pub deferred: usize,
+    pub join_time: Duration
+}
+
+/// In-flight tasks are sized from compute threads, joining is limited by frame time
+#[derive(Resource)]
+pub struct Scheduler {
+    /// In-flight tasks per compute thread
+    pub tasks_per_thread: f32,
+    /// Part of tasks used by generation when both queues aren't empty
+    pub load_share: f32,
+    /// Max join time per frame, at least one result is joined
+    pub join_budget: Duration,
+    pub stats: SchedulerStats
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self {
+            tasks_per_thread: 1.0,
+            load_share: 0.5,
+            join_budget: Duration::from_millis(4),
+            stats: SchedulerStats::default()
+        }
+    }
+}
+
+impl Scheduler {
+    /// Generate and mesh in-flight tasks limits, idle queue gives its tasks to other
+    pub fn limits(&mut self, threads: usize, loading: bool, building: bool) -> (usize, usize) {
+        let total = ((threads as f32 * self.tasks_per_thread).ceil() as usize).max(2);
+        let chunks = match (loading, building) {
+            (true, true) => ((total as f32 * self.load_share).round() as usize).clamp(1, total - 1),
+            (true, false) => total,
+            (false, _) => 0
+        };
+
+        self.stats.threads = threads;
+        (self.stats.max_chunks, self.stats.max_meshes) = (chunks, total - chunks);
+        (chunks, total - chunks)
+    }
+}
+
+pub fn ui(
+    mut contexts: EguiContexts,
+    mut scheduler: ResMut<Scheduler>
+) {
+    egui::Window::new("Tasks").default_open(false).show(contexts.ctx_mut(), |ui| {
+        ui.add(egui::Slider::new(&mut scheduler.tasks_per_thread, 0.25..=4.0).text("Tasks per thread"));
+        ui.add(egui::Slider::new(&mut scheduler.load_share, 0.1..=0.9).text("Generation share"));
+
+        let mut budget = scheduler.join_budget.as_secs_f32() * 1000.0;
+        if ui.add(egui::Slider::new(&mut budget, 0.5..=16.0).text("Join budget (ms)")).changed() {
+            scheduler.join_budget = Duration::from_secs_f32(budget / 1000.0);
+        }
+
+        let stats = &scheduler.stats;
+        ui.separator();
+        ui.label(format!("Threads: {}", stats.threads));
+        ui.label(format!("Generating: {}/{}", stats.load_tasks, stats.max_chunks));
+        ui.label(format!("Meshing: {}/{}", stats.build_tasks, stats.max_meshes));
+        ui.label(format!("Joined: {} chunks, {} meshes", stats.joined_chunks, stats.joined_meshes));
+        ui.label(format!("Deferred: {}", stats.deferred));
+        ui.label(format!("Join time: {:.2} ms", stats.join_time.as_secs_f32() * 1000.0));
+    });
+}
@@ -0,0 +1,582 @@
+//! Sponge schematic (.schem) import
+
+use std::io::Read;
+use std::path::Path;
+use bevy::{
+    prelude::*,
+    utils::HashMap,
+    window::FileDragAndDrop,
+};
+use flate2::read::GzDecoder;
+use serde::{Serialize, Deserialize};
+use super::*;
+
+/// Minimal NBT tag tree, only tags used by schematics are kept
+#[derive(Debug, Clone)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    ByteArray(Vec<i8>),
+    Compound(HashMap<String, Tag>),
+    IntArray(Vec<i32>),
+    /// Skipped tag (long, float, string, list etc)
+    Other,
+}
+
+impl Tag {
+    pub fn get(&self, name: &str) -> Option<&Tag> {
+        match self {
+            Self::Compound(map) => map.get(name),
+            _ => None
+        }
+    }
+
+    /// Any integer tag as i32
+    pub fn int(&self) -> Option<i32> {
+        match self {
+            Self::Byte(v) => Some(*v as i32),
+            Self::Short(v) => Some(*v as i32),
+            Self::Int(v) => Some(*v),
+            _ => None
+        }
+    }
+}
+
+/// Big-endian NBT reader
+struct NbtReader<'a> {
+    data: &'a [u8],
+    cursor: usize,
+    /// Nested compounds and lists
+    depth: usize
+}
+
+impl<'a> NbtReader<'a> {
+    /// Max nesting, deeper data is rejected instead of overflowing the stack
+    const MAX_DEPTH: usize = 512;
+
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, cursor: 0, depth: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if n > self.data.len() - self.cursor {
+            return Err("Unexpected end of NBT data".to_string());
+        }
+
+        let slice = &self.data[self.cursor..self.cursor + n];
+        self.cursor += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16, String> {
+        Ok(i16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> Result<usize, String> {
+        let len = self.i32()?;
+        if len < 0 {
+            return Err(format!("Negative NBT length {}", len));
+        }
+        Ok(len as usize)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.i16()? as u16 as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    /// Enter list or compound
+    fn enter(&mut self) -> Result<(), String> {
+        if self.depth == Self::MAX_DEPTH {
+            return Err(format!("NBT nesting is deeper than {}", Self::MAX_DEPTH));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn payload(&mut self, id: u8) -> Result<Tag, String> {
+        Ok(match id {
+            0 => Tag::Other,
+            1 => Tag::Byte(self.u8()? as i8),
+            2 => Tag::Short(self.i16()?),
+            3 => Tag::Int(self.i32()?),
+            4 | 6 => { self.take(8)?; Tag::Other },
+            5 => { self.take(4)?; Tag::Other },
+            7 => {
+                let len = self.len()?;
+                Tag::ByteArray(self.take(len)?.iter().map(|b| *b as i8).collect())
+            },
+            8 => { self.string()?; Tag::Other },
+            9 => {
+                self.enter()?;
+                let item = self.u8()?;
+                let len = self.len()?;
+                for _ in 0..len {
+                    self.payload(item)?;
+                }
+                self.depth -= 1;
+                Tag::Other
+            },
+            10 => {
+                self.enter()?;
+                let mut map = HashMap::new();
+                loop {
+                    let id = self.u8()?;
+                    if id == 0 { break; }
+                    let name = self.string()?;
+                    map.insert(name, self.payload(id)?);
+                }
+                self.depth -= 1;
+                Tag::Compound(map)
+            },
+            11 => {
+                let len = self.len()?;
+                let mut array = Vec::with_capacity(len.min(self.data.len() / 4));
+                for _ in 0..len { array.push(self.i32()?); }
+                Tag::IntArray(array)
+            },
+            12 => {
+                let len = self.len()?;
+                self.take(len.checked_mul(8).ok_or("Long array is too big")?)?;
+                Tag::Other
+            },
+            id => return Err(format!("Unknown NBT tag id {}", id))
+        })
+    }
+
+    /// Read named root tag
+    fn root(&mut self) -> Result<(String, Tag), String> {
+        let id = self.u8()?;
+        let name = self.string()?;
+        Ok((name, self.payload(id)?))
+    }
+}
+
+/// Decode Sponge varint-packed block data
+fn varints(data: &[i8]) -> Result<Vec<u32>, String> {
+    let mut result = Vec::with_capacity(data.len());
+    let (mut value, mut shift) = (0u32, 0u32);
+    for byte in data.iter().map(|b| *b as u8) {
+        value |= ((byte & 0x7F) as u32) << shift;
+        if byte & 0x80 == 0 {
+            result.push(value);
+            (value, shift) = (0, 0);
+        } else {
+            shift += 7;
+            if shift > 28 {
+                return Err("Varint is too long".to_string());
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Raw schematic data with its own block palette
+#[derive(Debug, Clone)]
+pub struct Schematic {
+    /// Width (X), Height (Y), Length (Z)
+    pub size: IVec3,
+    /// Paste offset relative to origin
+    pub offset: IVec3,
+    /// Palette index -> block state name
+    pub palette: Vec<String>,
+    /// Palette indices in YZX order
+    pub data: Vec<u32>
+}
+
+impl Schematic {
+    /// Limit for decompressed NBT size
+    const MAX_DECOMPRESSED: u64 = 64 << 20;
+
+    /// Read gzip-compressed .schem file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let file = std::fs::read(path).map_err(|e| e.to_string())?;
+        Self::from_bytes(&file)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut raw = Vec::with_capacity(bytes.len() * 4);
+        GzDecoder::new(bytes).take(Self::MAX_DECOMPRESSED + 1).read_to_end(&mut raw).map_err(|e| e.to_string())?;
+        if raw.len() as u64 > Self::MAX_DECOMPRESSED {
+            return Err(format!("Decompressed schematic is larger than {} bytes", Self::MAX_DECOMPRESSED));
+        }
+
+        let (_, root) = NbtReader::new(&raw).root()?;
+        // Version 3 wraps everything into "Schematic" compound
+        let root = root.get("Schematic").unwrap_or(&root);
+
+        let dim = |name: &str| root.get(name).and_then(Tag::int)
+            .ok_or(format!("Missing {}", name));
+        let size = IVec3::new(dim("Width")? as u16 as i32, dim("Height")? as u16 as i32, dim("Length")? as u16 as i32);
+
+        let offset = match root.get("Offset") {
+            Some(Tag::IntArray(v)) if v.len() == 3 => IVec3::new(v[0], v[1], v[2]),
+            _ => IVec3::ZERO
+        };
+
+        // Version 2 stores palette in root, version 3 in "Blocks"
+        let (palette, data) = match root.get("Blocks") {
+            Some(blocks) => (blocks.get("Palette"), blocks.get("Data")),
+            None => (root.get("Palette"), root.get("BlockData"))
+        };
+
+        let Some(Tag::Compound(palette)) = palette else {
+            return Err("Missing block palette".to_string());
+        };
+        let Some(Tag::ByteArray(data)) = data else {
+            return Err("Missing block data".to_string());
+        };
+
+        let mut names = vec![String::new(); palette.len()];
+        for (name, index) in palette.iter() {
+            let index = index.int().ok_or("Wrong palette entry")? as usize;
+            if index >= names.len() {
+                return Err(format!("Palette index {} out of range", index));
+            }
+            names[index] = name.clone();
+        }
+
+        let data = varints(data)?;
+        let volume = (size.x as usize).checked_mul(size.y as usize)
+            .and_then(|v| v.checked_mul(size.z as usize))
+            .ok_or("Schematic is too big")?;
+        if data.len() != volume {
+            return Err("Block data doesn't match schematic size".to_string());
+        }
+
+        Ok(Self { size, offset, palette: names, data })
+    }
+
+    /// Map palette to world block ids
+    pub fn map(&self, mapping: &BlockMapping, blocks: &BlocksHandler) -> (PasteBuffer, Vec<String>) {
+        let mut unmapped = Vec::new();
+        let palette: Vec<Option<u16>> = self.palette.iter().map(|name| {
+            let id = mapping.get(name).and_then(|b| blocks.get(b));
+            if id.is_none() { unmapped.push(name.clone()); }
+            id
+        }).collect();
+
+        let mut buffer = PasteBuffer { size: self.size, offset: self.offset, blocks: Vec::with_capacity(self.data.len()) };
+        for index in self.data.iter() {
+            buffer.blocks.push(palette.get(*index as usize).copied().flatten());
+        }
+
+        (buffer, unmapped)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+/// Schematic block state name -> world block name
+pub struct BlockMapping(pub HashMap<String, String>);
+
+impl Default for BlockMapping {
+    fn default() -> Self {
+        Self(HashMap::from_iter([
+            ("minecraft:air", "Air"),
+            ("minecraft:cave_air", "Air"),
+            ("minecraft:void_air", "Air"),
+            ("minecraft:dirt", "Dirt"),
+            ("minecraft:grass_block", "Grass"),
+            ("minecraft:stone", "Stone"),
+        ].map(|(k, v)| (k.to_string(), v.to_string()))))
+    }
+}
+
+impl BlockMapping {
+    pub const FILE: &'static str = "mapping.yaml";
+
+    /// Load mapping from world folder (default if not exists)
+    pub fn load(world: &str) -> Self {
+        let path = format!("./worlds/{}/{}", world, Self::FILE);
+        std::fs::read_to_string(path).ok()
+            .and_then(|s| serde_yaml::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    /// Find block by full state name, then without state properties
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.0.get(name).or_else(|| {
+            let base = name.split('[').next()?;
+            self.0.get(base)
+        })
+    }
+}
+
+/// Mapped schematic ready to be pasted; None is unmapped block (skipped)
+#[derive(Clone)]
+pub struct PasteBuffer {
+    pub size: IVec3,
+    pub offset: IVec3,
+    pub blocks: Vec<Option<u16>>
+}
+
+impl PasteBuffer {
+    /// All blocks with global positions
+    pub fn iter(&self, origin: IVec3) -> impl Iterator<Item = (IVec3, u16)> + '_ {
+        let (w, l) = (self.size.x, self.size.z);
+        let origin = origin + self.offset;
+        self.blocks.iter().enumerate().filter_map(move |(i, b)| {
+            let i = i as i32;
+            let pos = IVec3::new(i % w, i / (w * l), (i / w) % l);
+            b.map(|b| (origin + pos, b))
+        })
+    }
+}
+
+#[derive(Resource, Default)]
+/// Last imported schematic
+pub struct Clipboard(pub Option<PasteBuffer>);
+
+/// Import .schem files dropped on window
+pub fn import(
+    mut events: EventReader<FileDragAndDrop>,
+    mut clipboard: ResMut<Clipboard>,
+    world: Res<WorldRes>
+) {
+    for ev in events.read() {
+        let FileDragAndDrop::DroppedFile { path_buf, .. } = ev else { continue };
+        if path_buf.extension().is_none_or(|e| e != "schem") { continue; }
+
+        match Schematic::open(path_buf) {
+            Ok(schematic) => {
+                let mapping = BlockMapping::load(&world.name);
+                let (buffer, unmapped) = schematic.map(&mapping, &world.blocks);
+                if !unmapped.is_empty() {
+                    println!("Unmapped schematic blocks: {:?}", unmapped);
+                }
+
+                println!("Schematic {:?} imported: {}", path_buf, schematic.size);
+                clipboard.0 = Some(buffer);
+            },
+            Err(e) => println!("Schematic import error: {}", e)
+        }
+    }
+}
+
+/// Paste clipboard at selected block
+pub fn paste(
+    mut controller: ResMut<Controller>,
+    clipboard: Res<Clipboard>,
+    selected: Res<systems::SelectedData>,
+    kbd: Res<ButtonInput<KeyCode>>,
+) {
+    if !kbd.just_pressed(KeyCode::KeyV) { return; }
+    let Some(buffer) = &clipboard.0 else { return };
+    let Some(origin) = selected.target() else { return };
+
+    let skipped = controller.set_blocks(buffer.iter(origin));
+    if skipped != 0 {
+        println!("{} blocks weren't pasted: chunks aren't loaded", skipped);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use flate2::{write::GzEncoder, Compression};
+    use super::*;
+
+    fn name(out: &mut Vec<u8>, name: &str) {
+        out.extend((name.len() as u16).to_be_bytes());
+        out.extend(name.as_bytes());
+    }
+
+    fn short(out: &mut Vec<u8>, key: &str, v: i16) {
+        out.push(2);
+        name(out, key);
+        out.extend(v.to_be_bytes());
+    }
+
+    fn int(out: &mut Vec<u8>, key: &str, v: i32) {
+        out.push(3);
+        name(out, key);
+        out.extend(v.to_be_bytes());
+    }
+
+    fn bytes(out: &mut Vec<u8>, key: &str, v: &[u8]) {
+        out.push(7);
+        name(out, key);
+        out.extend((v.len() as i32).to_be_bytes());
+        out.extend(v);
+    }
+
+    fn ints(out: &mut Vec<u8>, key: &str, v: &[i32]) {
+        out.push(11);
+        name(out, key);
+        out.extend((v.len() as i32).to_be_bytes());
+        v.iter().for_each(|i| out.extend(i.to_be_bytes()));
+    }
+
+    fn compound(out: &mut Vec<u8>, key: &str, body: impl FnOnce(&mut Vec<u8>)) {
+        out.push(10);
+        name(out, key);
+        body(out);
+        out.push(0);
+    }
+
+    fn gzip(raw: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(raw).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn palette(out: &mut Vec<u8>) {
+        compound(out, "Palette", |out| {
+            int(out, "minecraft:air", 0);
+            int(out, "minecraft:stone", 1);
+            int(out, "minecraft:oak_log[axis=y]", 300);
+            for i in 2..300 {
+                int(out, &format!("minecraft:filler_{}", i), i);
+            }
+        });
+    }
+
+    /// 2x1x2 blocks: air, stone, log (index 300 is two varint bytes), stone
+    const DATA: [u8; 5] = [0x00, 0x01, 0xAC, 0x02, 0x01];
+
+    fn common(out: &mut Vec<u8>, version: i32) {
+        int(out, "Version", version);
+        short(out, "Width", 2);
+        short(out, "Height", 1);
+        short(out, "Length", 2);
+        ints(out, "Offset", &[1, -2, 3]);
+        // Skipped tags: long, float, string and list of compounds
+        out.push(4); name(out, "Date"); out.extend(7i64.to_be_bytes());
+        out.push(5); name(out, "Scale"); out.extend(1f32.to_be_bytes());
+        out.push(8); name(out, "Author"); name(out, "someone");
+        out.push(9); name(out, "Entities"); out.push(10); out.extend(1i32.to_be_bytes());
+        int(out, "Id", 1);
+        out.push(0);
+    }
+
+    fn check(schematic: &Schematic) {
+        assert_eq!(schematic.size, IVec3::new(2, 1, 2));
+        assert_eq!(schematic.offset, IVec3::new(1, -2, 3));
+        assert_eq!(schematic.data, vec![0, 1, 300, 1]);
+        assert_eq!(schematic.palette.len(), 301);
+        assert_eq!(schematic.palette[1], "minecraft:stone");
+        assert_eq!(schematic.palette[300], "minecraft:oak_log[axis=y]");
+    }
+
+    #[test]
+    fn version_2() {
+        let mut raw = Vec::new();
+        compound(&mut raw, "Schematic", |out| {
+            common(out, 2);
+            int(out, "PaletteMax", 301);
+            palette(out);
+            bytes(out, "BlockData", &DATA);
+        });
+
+        check(&Schematic::from_bytes(&gzip(&raw)).unwrap());
+    }
+
+    #[test]
+    fn version_3() {
+        let mut raw = Vec::new();
+        compound(&mut raw, "", |out| compound(out, "Schematic", |out| {
+            common(out, 3);
+            compound(out, "Blocks", |out| {
+                palette(out);
+                bytes(out, "Data", &DATA);
+            });
+        }));
+
+        check(&Schematic::from_bytes(&gzip(&raw)).unwrap());
+    }
+
+    #[test]
+    fn wrong_size() {
+        let mut raw = Vec::new();
+        compound(&mut raw, "Schematic", |out| {
+            short(out, "Width", -1);
+            short(out, "Height", -1);
+            short(out, "Length", -1);
+            palette(out);
+            bytes(out, "BlockData", &DATA);
+        });
+
+        assert!(Schematic::from_bytes(&gzip(&raw)).is_err());
+    }
+
+    #[test]
+    fn multi_byte_varints() {
+        let data = [0x00, 0x7F, 0x80, 0x01, 0xAC, 0x02, 0xFF, 0xFF, 0x03, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F];
+        let data = data.map(|b| b as i8);
+        assert_eq!(varints(&data).unwrap(), vec![0, 127, 128, 300, 65535, u32::MAX]);
+
+        let long = [0x80u8, 0x80, 0x80, 0x80, 0x80, 0x01].map(|b| b as i8);
+        assert!(varints(&long).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let mut raw = Vec::new();
+        compound(&mut raw, "Schematic", |out| bytes(out, "BlockData", &DATA));
+        raw.truncate(raw.len() - 3);
+
+        assert!(NbtReader::new(&raw).root().is_err());
+    }
+
+    #[test]
+    fn decompressed_limit() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        let zeros = vec![0; 1 << 20];
+        for _ in 0..=Schematic::MAX_DECOMPRESSED >> 20 {
+            encoder.write_all(&zeros).unwrap();
+        }
+        let bytes = encoder.finish().unwrap();
+
+        assert!(Schematic::from_bytes(&bytes).unwrap_err().contains("larger"));
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let depth = NbtReader::MAX_DEPTH + 1;
+        let mut raw = vec![9];
+        name(&mut raw, "");
+        for _ in 0..depth {
+            raw.push(9);
+            raw.extend(1i32.to_be_bytes());
+        }
+        raw.push(0);
+        raw.extend(0i32.to_be_bytes());
+
+        assert!(NbtReader::new(&raw).root().unwrap_err().contains("deeper"));
+    }
+
+    #[test]
+    fn state_mapping() {
+        let mapping = BlockMapping::default();
+        assert_eq!(mapping.get("minecraft:stone").map(String::as_str), Some("Stone"));
+        assert_eq!(mapping.get("minecraft:grass_block[snowy=false]").map(String::as_str), Some("Grass"));
+        assert_eq!(mapping.get("minecraft:oak_log[axis=y]"), None);
+        assert_eq!(mapping.get("minecraft:oak_log"), None);
+    }
+
+    #[test]
+    fn paste_order() {
+        let buffer = PasteBuffer {
+            size: IVec3::new(2, 2, 2),
+            offset: IVec3::ONE,
+            blocks: (0..8).map(|i| (i != 3).then_some(i)).collect()
+        };
+
+        let blocks: Vec<_> = buffer.iter(IVec3::ZERO).collect();
+        assert_eq!(blocks.len(), 7);
+        assert_eq!(blocks[0], (IVec3::new(1, 1, 1), 0));
+        assert_eq!(blocks[1], (IVec3::new(2, 1, 1), 1));
+        assert_eq!(blocks[2], (IVec3::new(1, 1, 2), 2));
+        assert_eq!(blocks[3], (IVec3::new(1, 2, 1), 4));
+    }
+}
@@ -1,9 +1,12 @@
 use bevy::{
     core_pipeline::Skybox, 
+    math::FloatOrd,
     prelude::*,
     render::{
         primitives::*,
-        render_resource::*
+        render_asset::RenderAssetUsages,
+        render_resource::*,
+        storage::ShaderStorageBuffer
     },
     tasks::*, 
     window::*
@@ -31,6 +34,7 @@ pub fn load_world(
 ) {
     commands.insert_resource(Controller::default());
     commands.insert_resource(SelectedData::empty());
+    commands.insert_resource(hotbar::Hotbar::new(&world.blocks));
     commands.insert_resource(AmbientLight {
         color: Color::Srgba(Srgba::rgb_u8(210, 220, 240)),
         brightness: 1200.0,
@@ -77,170 +81,385 @@ pub fn skybox(
     }
 }
 
-/// Max thread tasks;
-pub const MAX_CHUNKS: usize = 4;
-pub const MAX_MESHES: usize = 2;
+/// Camera turn angle which resorts queues
+pub const RESORT_TURN: f32 = std::f32::consts::PI / 12.0;
+
 // Begin tasks
 pub fn begin(
     mut controller: ResMut<Controller>,
-    cameras: Query<Ref<Transform>, With<Camera3d>>,
-    world: Res<WorldRes>
+    mut scheduler: ResMut<scheduler::Scheduler>,
+    cameras: Query<(&Transform, &Frustum), With<Camera3d>>,
+    world: Res<WorldRes>,
+    mut sorted_view: Local<Option<(IVec3, Vec3)>>
 ) {
     let task_pool = ComputeTaskPool::get();
 
-    // Sort load-build queues
-    if controller.need_sort {
-        let current = RawChunk::global(cameras.single().translation);
-        controller.load.sort_by(|a, b| 
-            a.distance_squared(current).cmp(&b.distance_squared(current)));
-        controller.build.sort_by(|a, b| 
-            a.distance_squared(current).cmp(&b.distance_squared(current)));
+    // Sort load-build queues, again when camera enters other chunk or turns
+    let (camera, frustum) = cameras.single();
+    let view = (RawChunk::global(camera.translation), *camera.forward());
+    let turned = sorted_view.is_none_or(|(chunk, forward)| chunk != view.0 || forward.dot(view.1) < RESORT_TURN.cos());
+    if controller.need_sort || turned {
+        // Priority is computed once per chunk, not per comparison
+        let priority = |pos: &IVec3| FloatOrd(Controller::priority(*pos, camera, frustum));
+        controller.load.sort_by_cached_key(priority);
+        controller.build.sort_by_cached_key(|pos, _| priority(pos));
 
         controller.need_sort = false;
+        *sorted_view = Some(view);
     }
 
+    let (max_chunks, max_meshes) = scheduler.limits(task_pool.thread_num(), !controller.load.is_empty(), !controller.build.is_empty());
+
     // Chunks queue
-    let l = (MAX_CHUNKS - controller.load_tasks.len()).min(controller.load.len());
+    let l = max_chunks.saturating_sub(controller.load_tasks.len()).min(controller.load.len());
     let mut to_remove = Vec::new();
     for i in 0..l {
         let Some(pos) = controller.load.get_index(i).cloned() else { continue };
-        controller.load_tasks.insert(pos, task_pool.spawn(RawChunk::generate(world.blocks.clone(), pos)));
+        let task = task_pool.spawn(RawChunk::generate(world.blocks.clone(), pos));
+        let generation = controller.generation(pos);
+        controller.load_tasks.insert(pos, ChunkTask { generation, sections: 0, task });
+        controller.set_state(pos, Some(ChunkState::Generating));
         to_remove.push(pos);
     }
     for pos in to_remove { controller.load.remove(&pos); }
 
     // Meshes queue
-    let b = (MAX_MESHES - controller.build_tasks.len()).min(controller.build.len());
-    
-    for i in 0..b {
-        let Some(pos) = controller.build.get_index(i).cloned() else { continue };
-        if let Some(refs) = controller.refs(pos) {
-            // Clear queue    
-            controller.build.remove(&pos);
-
-            // Create mesh build task
-            controller.build_tasks.insert(pos, task_pool.spawn(ChunkMesh::build(world.blocks.clone(), refs)));
+    let b = max_meshes.saturating_sub(controller.build_tasks.len());
+    let ready: Vec<_> = controller.build.keys()
+        .filter_map(|pos| Some((*pos, controller.refs(*pos)?)))
+        .take(b)
+        .collect();
+
+    for (pos, refs) in ready {
+        // Clear queue
+        let Some(mut sections) = controller.build.remove(&pos) else { continue };
+        // Outdated running build is cancelled, its sections are built by new one
+        if let Some(outdated) = controller.build_tasks.remove(&pos) {
+            sections |= outdated.sections;
+        }
+
+        // Create mesh build task, LOD mesh is rebuilt whole
+        let (lod, borders, strategy) = (controller.lod(pos), controller.lod_borders(pos), controller.strategy);
+        if controller.lods.get(&pos) != Some(&lod) || lod > 0 {
+            sections = ALL_SECTIONS;
+        }
+        let task = task_pool.spawn(ChunkMesh::build(world.blocks.clone(), refs, lod, borders, strategy, sections));
+        let generation = controller.mesh_generation(pos);
+        controller.build_tasks.insert(pos, ChunkTask { generation, sections, task });
+        controller.set_state(pos, Some(ChunkState::Meshing));
+    }
+
+    scheduler.stats.load_tasks = controller.load_tasks.len();
+    scheduler.stats.build_tasks = controller.build_tasks.len();
+}
+
+/// Rebuild chunks which level of detail is changed by camera move
+pub fn update_lod(
+    mut controller: ResMut<Controller>,
+    cameras: Query<&Transform, With<MainCamera>>
+) {
+    let Ok(camera) = cameras.get_single() else { return };
+    let current = RawChunk::global(camera.translation);
+    if current == controller.camera { return; }
+    controller.camera = current;
+
+    let changed: Vec<_> = controller.lods.iter()
+        .filter(|(pos, lod)| controller.lod(**pos) != **lod)
+        .map(|(pos, _)| *pos)
+        .collect();
+    if !changed.is_empty() {
+        for pos in changed {
+            controller.queue(pos, ALL_SECTIONS);
+            // Full detail neighbours uncull or cull faces on their common side
+            for dir in Direction::iter() {
+                let neighbour = pos + dir.air_sample();
+                if controller.lods.get(&neighbour) == Some(&0) && controller.lod(neighbour) == 0 {
+                    controller.queue(neighbour, ALL_SECTIONS);
+                }
+            }
+        }
+        controller.sort();
+    }
+}
+
+/// Load chunks in LOAD_RADIUS around camera chunk and queue their meshes (one chunk closer,
+/// so neighbours are loaded), unload chunks out of radius
+pub fn update_radius(
+    mut controller: ResMut<Controller>,
+    cameras: Query<&Transform, With<MainCamera>>,
+    mut last: Local<Option<IVec3>>
+) {
+    let Ok(camera) = cameras.get_single() else { return };
+    let current = RawChunk::global(camera.translation);
+    if *last == Some(current) { return; }
+    *last = Some(current);
+
+    let distance = |pos: IVec3| (pos - current).abs().max_element();
+    let outside: Vec<_> = controller.states.iter()
+        .filter(|(pos, state)| distance(**pos) > LOAD_RADIUS && **state != ChunkState::Unloading)
+        .map(|(pos, _)| *pos)
+        .collect();
+    for pos in outside {
+        controller.request_unload(pos);
+    }
+
+    let r = LOAD_RADIUS;
+    for x in -r..=r {
+        for y in -r..=r {
+            for z in -r..=r {
+                let pos = current + IVec3::new(x, y, z);
+                if controller.state(pos).is_none() {
+                    controller.load.insert(pos);
+                    controller.set_state(pos, Some(ChunkState::Queued));
+                }
+
+                // Chunks which mesh was never built or queued
+                let unbuilt = matches!(controller.state(pos), Some(ChunkState::Queued | ChunkState::Generating | ChunkState::Decorating | ChunkState::Generated));
+                if distance(pos) < r && unbuilt && !controller.build.contains_key(&pos) {
+                    controller.queue(pos, ALL_SECTIONS);
+                }
+            }
         }
     }
+    controller.sort();
 }
 
 pub fn unload(
     mut controller: ResMut<Controller>,
     mut commands: Commands
 ) {
+    let chunks: Vec<_> = controller.unload.drain(..).collect();
+    for chunk in chunks {
+        controller.unload_chunk(chunk);
+    }
+
     for entity in controller.despawn.drain(..) {
-        commands.entity(entity).despawn();
+        commands.entity(entity).despawn_recursive();
     }
 }
 
 pub fn join(
     mut commands: Commands,
     mut controller: ResMut<Controller>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    world: Res<WorldRes>,
+    mut buffers: ResMut<Assets<ShaderStorageBuffer>>,
+    sections: Query<&ChunkQuads>,
+    mut scheduler: ResMut<scheduler::Scheduler>,
 ) {
+    // Finished results over frame budget are joined next frame
+    let start = Instant::now();
+    let budget = scheduler.join_budget;
+    let over_budget = |joined: usize| joined > 0 && start.elapsed() > budget;
+    let (mut chunks, mut meshes, mut deferred) = (0, 0, 0);
+
     // join chunks;
     let data: Vec<_> = controller.load_tasks.drain().collect();
     for (pos, task) in data {
-        if !task.is_finished() {
+        if !task.task.is_finished() || over_budget(chunks + meshes) {
+            deferred += task.task.is_finished() as usize;
             controller.load_tasks.insert(pos, task);
             continue;
         }
-        
-        let raw = block_on(task);
+
+        // Unload drops chunk task, so finished task is always current
+        let raw = block_on(task.task);
         controller.chunks.insert(pos, Chunk::new(raw));
+        // Decoration is no-op yet, chunk passes its state at once
+        controller.set_state(pos, Some(ChunkState::Decorating));
+        controller.set_state(pos, Some(ChunkState::Generated));
+        chunks += 1;
     }
 
     // join meshes
     let data: Vec<_> = controller.build_tasks.drain().collect();
     for (pos, task) in data {
-        if !task.is_finished() { 
+        if !task.task.is_finished() || over_budget(chunks + meshes) {
+            deferred += task.task.is_finished() as usize;
             controller.build_tasks.insert(pos, task);
             continue;
         }
-        
-        // Remove current mesh first chunks 
-        if let Some(old) = controller.meshes.remove(&pos) {
-            controller.despawn.push(old);
-        };
-
-        // Spawn new mesh
-        if let Some(mesh) = block_on(task) {
-            let handler = meshes.add(mesh);
-            let entity = commands.spawn((
-                Aabb::from_min_max(Vec3::splat(-RawChunk::SIZE_F32/2.0), Vec3::splat(RawChunk::SIZE_F32*1.5)),
-                Mesh3d(handler),
-                MeshMaterial3d(world.main_material.clone()),
-                Transform::from_translation(pos.as_vec3() * Vec3::splat(RawChunk::SIZE_F32))
-            )).id();
-
-            controller.meshes.insert(pos, entity);
+        meshes += 1;
+
+        // Chunk was requeued or unloaded since task spawn, newer build will cover its sections
+        let (visibility, results) = block_on(task.task);
+        if task.generation != controller.mesh_generation(pos) {
+            if controller.chunks.contains_key(&pos) {
+                *controller.build.entry(pos).or_default() |= task.sections;
+                // Waits in build queue with its previous mesh if it has one
+                let state = if controller.lods.contains_key(&pos) { ChunkState::Ready } else { ChunkState::Generated };
+                controller.set_state(pos, Some(state));
+            }
+            continue;
+        }
+        controller.visibility.insert(pos, visibility);
+        controller.set_state(pos, Some(ChunkState::Ready));
+        let Some(lod) = results.first().map(|r| r.lod) else { continue };
+        controller.lods.insert(pos, lod);
+
+        let origin = pos.as_vec3() * Vec3::splat(RawChunk::SIZE_F32);
+        let mut entities = controller.meshes.remove(&pos).unwrap_or_default();
+        for mut result in results {
+            let entities = &mut entities[result.section];
+            for ((opacity, pass), entity) in ChunkMeshes::PASSES.into_iter().zip(result.passes).zip(entities.iter_mut()) {
+                let quads = if opacity == Opacity::Translucent { result.quads.take() } else { None };
+                match (pass, *entity) {
+                    (None, None) => {}
+                    // Pass became empty
+                    (None, Some(old)) => {
+                        controller.despawn.push(old);
+                        *entity = None;
+                    }
+                    // Update buffer asset in place, so there is no frame without mesh
+                    (Some((mesh, aabb)), Some(current)) if sections.contains(current) => {
+                        let section = sections.get(current).unwrap();
+                        buffers.insert(&section.buffer, mesh.buffer(origin, RenderAssetUsages::RENDER_WORLD));
+                        let section = ChunkQuads {
+                            buffer: section.buffer.clone(),
+                            quads: mesh.quads(),
+                            opacity,
+                            center: origin + Vec3::from(aabb.center)
+                        };
+
+                        let mut current = commands.entity(current);
+                        current.insert((section, aabb));
+                        if let Some(quads) = quads {
+                            current.insert(quads);
+                        }
+                    }
+                    // New pass, or entity without section data is replaced
+                    (Some((mesh, aabb)), old) => {
+                        controller.despawn.extend(old);
+                        let section = ChunkQuads {
+                            buffer: buffers.add(mesh.buffer(origin, RenderAssetUsages::RENDER_WORLD)),
+                            quads: mesh.quads(),
+                            opacity,
+                            center: origin + Vec3::from(aabb.center)
+                        };
+                        let mut new = commands.spawn((
+                            section,
+                            aabb,
+                            Visibility::default(),
+                            Transform::from_translation(origin)
+                        ));
+                        if let Some(quads) = quads {
+                            new.insert(quads);
+                        }
+                        *entity = Some(new.id());
+                    }
+                }
+            }
+        }
+
+        if entities.iter().flatten().any(|e| e.is_some()) {
+            controller.meshes.insert(pos, entities);
         }
     }
+
+    let stats = &mut scheduler.stats;
+    (stats.joined_chunks, stats.joined_meshes, stats.deferred) = (chunks, meshes, deferred);
+    stats.join_time = start.elapsed();
+}
+
+/// Send chunk state transitions recorded by controller
+pub fn chunk_events(
+    mut controller: ResMut<Controller>,
+    mut events: EventWriter<ChunkStateChanged>
+) {
+    if controller.transitions.is_empty() { return; }
+    events.send_batch(controller.transitions.drain(..));
+}
+
+/// Sort translucent quads back-to-front when camera moves to other block
+pub fn sort_translucent(
+    cameras: Query<&Transform, With<Camera3d>>,
+    quads: Query<(Ref<TranslucentQuads>, &ChunkQuads, &Transform), Without<Camera3d>>,
+    mut buffers: ResMut<Assets<ShaderStorageBuffer>>,
+    mut last: Local<Option<IVec3>>
+) {
+    let eye = cameras.single().translation;
+    let moved = *last != Some(eye.floor().as_ivec3());
+    *last = Some(eye.floor().as_ivec3());
+
+    for (quads, section, chunk) in quads.iter() {
+        if !moved && !quads.is_changed() { continue; }
+
+        let sorted = quads.sorted(eye - chunk.translation);
+        buffers.insert(&section.buffer, sorted.buffer(chunk.translation, RenderAssetUsages::RENDER_WORLD));
+    }
 }
 
 pub fn hot_reload(
     mut controller: ResMut<Controller>,
-    mut images: EventReader<AssetEvent<Image>>,
     mut worlds_events: EventReader<AssetEvent<WorldData>>,
 
     assets: Res<AssetServer>,
     worlds: Res<Assets<WorldData>>,
-    mut materials: ResMut<Assets<ChunkMaterial>>,
     mut world: ResMut<WorldRes>,
 ) {
     for ev in worlds_events.read() {
         if ev.is_modified(&world.handler) {
-            // Get updated world data
+            // Get updated world data, models are baked by bake_models
             let data = worlds.get(&world.handler).unwrap();
-            let blocks = BlocksHandler::new(&assets, data.blocks.clone());
-            // Recreate material
-            let material = materials.add(ChunkMaterial::new(&blocks));
-
-            world.blocks = blocks;
-            world.main_material = material;
+            world.blocks = BlocksHandler::new(&assets, data.blocks.clone());
             controller.reload();
         }
     }
-
-    if !images.is_empty() {
-        controller.reload();
-        images.clear();
-    }
 }
 
-struct SelectedBlock {
-    chunk: IVec3,
-    block: usize,
-    data: u16
+/// Rebake custom models and recreate texture array on world or models change
+pub fn bake_models(
+    mut controller: ResMut<Controller>,
+    mut models_events: EventReader<AssetEvent<CustomModel>>,
+    mut worlds_events: EventReader<AssetEvent<WorldData>>,
+
+    models: Res<Assets<CustomModel>>,
+    mut images: ResMut<Assets<Image>>,
+    mut world: ResMut<WorldRes>,
+) {
+    let world_changed = worlds_events.read().any(|ev| ev.is_modified(&world.handler));
+    let models_changed = models_events.read().any(|ev| matches!(ev, AssetEvent::Modified { .. }));
+    if !world_changed && !models_changed { return; }
+
+    world.blocks = world.blocks.bake(&models);
+    world.update_textures(&mut images);
+    controller.reload();
 }
 
-impl SelectedBlock {
-    pub fn empty() -> Self {
-        Self {
-            chunk: IVec3::ZERO,
-            block: 0,
-            data: 0
-        }
-    }
+/// Rebuild texture array when any block texture is loaded or changed
+pub fn reload_textures(
+    mut controller: ResMut<Controller>,
+    mut images_events: EventReader<AssetEvent<Image>>,
+    mut images: ResMut<Assets<Image>>,
+    mut world: ResMut<WorldRes>,
+) {
+    // Texture array itself is an image too, so check only blocks textures
+    let textures = world.blocks.textures();
+    let changed = images_events.read().any(|ev| textures.iter().any(|t| ev.is_modified(t) || ev.is_loaded_with_dependencies(t)));
+    if !changed { return; }
 
-    pub fn set(&mut self, chunk: IVec3, block: usize, data: u16) {
-        (self.chunk, self.block, self.data) = (chunk, block, data);
-    }
+    world.update_textures(&mut images);
+    controller.reload();
 }
 
+/// Max block selection distance
+pub const SELECT_DISTANCE: f32 = 32.0;
+
 #[derive(Resource)]
 pub struct SelectedData {
-    previous: SelectedBlock,
-    current: SelectedBlock
+    /// Block under cursor
+    pub current: Option<RayHit>
 }
 
 impl SelectedData {
     pub fn empty() -> Self {
-        Self { previous: SelectedBlock::empty(), current: SelectedBlock::empty() }
+        Self { current: None }
     }
 
     pub fn reset(&mut self) {*self = Self::empty();}
+
+    /// Global pos of empty block in front of selected face
+    pub fn target(&self) -> Option<IVec3> {
+        self.current.filter(|hit| hit.normal != IVec3::ZERO).map(|hit| hit.adjacent())
+    }
 }
 
 pub fn update_selected(
@@ -251,25 +470,9 @@ pub fn update_selected(
     let camera = cameras.single();
     let current = camera.translation();
     let u = camera.forward().normalize();
-    let blocks = RawChunk::under_cursor(current, u, 32);
-    
-    // Reset selected blocks
+
     selected.reset();
-    for block in blocks {
-        let chunk_pos = RawChunk::global(block);
-        let index = RawChunk::block_index(RawChunk::relative(block));
-
-        if let Some(chunk) = controller.chunks.get(&chunk_pos).cloned() {
-            let guard = chunk.read();
-            let data = guard.get()[index];
-            if data == 0 {
-                selected.previous.set(chunk_pos, index, data);
-            } else {
-                selected.current.set(chunk_pos, index, data);
-                break;
-            }
-        }
-    }
+    selected.current = controller.raycast(current, u, SELECT_DISTANCE);
 }
 
 pub fn keybind(
@@ -278,7 +481,9 @@ pub fn keybind(
     kbd: Res<ButtonInput<KeyCode>>,
     mouse_buttons: Res<ButtonInput<MouseButton>>,
     cameras: Query<Ref<GlobalTransform>, With<Camera3d>>,
-    selected: Res<SelectedData>
+    selected: Res<SelectedData>,
+    brush: Res<brush::Brush>,
+    hotbar: Res<hotbar::Hotbar>
 ) {
     let camera = cameras.single();
     
@@ -286,6 +491,19 @@ pub fn keybind(
         controller.reload();
     }
 
+    // Switch mesher and rebuild
+    if kbd.just_pressed(KeyCode::KeyM) {
+        controller.strategy = controller.strategy.next();
+        println!("Mesh strategy: {:?};", controller.strategy);
+        controller.reload();
+    }
+
+    // Toggle cave culling for debugging
+    if kbd.just_pressed(KeyCode::KeyO) {
+        controller.culling = !controller.culling;
+        println!("Cave culling: {};", controller.culling);
+    }
+
     if kbd.just_pressed(KeyCode::Escape) {
         if let Some(mut window) = primary_window.get_single_mut().ok() {
             if window.cursor_options.visible {
@@ -299,40 +517,93 @@ pub fn keybind(
     }
 
     // Destroy block
-    if mouse_buttons.just_pressed(MouseButton::Left) {
-        if selected.current.data != 0 {
-            if let Some(chunk) = controller.chunks.get(&selected.current.chunk) {
-                let mut guard = chunk.write();
-                guard.get_mut()[selected.current.block] = 0;
-            }
-            controller.rebuild(selected.current.chunk);
+    if mouse_buttons.just_pressed(MouseButton::Left) && !brush.enabled {
+        if let Some(hit) = selected.current {
+            controller.set_blocks([(hit.block, 0)]);
         }
     }
 
     // Place block
-    if mouse_buttons.just_pressed(MouseButton::Right) {
-        if selected.current.data != 0 {
-            if let Some(chunk) = controller.chunks.get(&selected.previous.chunk) {
-                let mut guard = chunk.write();
-                guard.get_mut()[selected.previous.block] = 3;
-            }
-            controller.rebuild(selected.previous.chunk);
+    if mouse_buttons.just_pressed(MouseButton::Right) && !brush.enabled {
+        if let Some(target) = selected.target() {
+            controller.set_blocks([(target, hotbar.selected())]);
         }
     }
 
     if kbd.just_pressed(KeyCode::KeyF) {
         let current = camera.translation();
         let u = camera.forward().normalize();
-        let blocks = RawChunk::under_cursor(current, u, 320);    
-        
-        for block in blocks {
-            let chunk_pos = RawChunk::global(block);
-            let index = RawChunk::block_index(RawChunk::relative(block));
-            if let Some(chunk) = controller.chunks.get(&chunk_pos) {
-                let mut guard =  chunk.write();
-                guard.get_mut()[index] = 3;
-            }
-            controller.rebuild(chunk_pos);
-        }    
+        let blocks = VoxelRay::new(current, u).skip(1).take(320);
+
+        controller.set_blocks(blocks.map(|step| (step.voxel, hotbar.selected())));
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+    use super::*;
+
+    #[test]
+    fn queue_keeps_data_generation() {
+        let mut controller = Controller::default();
+        let pos = IVec3::new(1, 2, 3);
+        let (data, mesh) = (controller.generation(pos), controller.mesh_generation(pos));
+
+        // Rebuild of chunk which is still generating doesn't drop its data
+        controller.queue(pos, ALL_SECTIONS);
+        assert_eq!(controller.generation(pos), data);
+        assert_ne!(controller.mesh_generation(pos), mesh);
+
+        controller.unload_chunk(pos);
+        assert!(!controller.generations.contains_key(&pos) && !controller.mesh_generations.contains_key(&pos));
+    }
+
+    #[test]
+    fn radius() {
+        let mut world = World::new();
+        world.insert_resource(Controller::default());
+        let camera = world.spawn((MainCamera::new(), Transform::default())).id();
+        let count = (2 * LOAD_RADIUS + 1).pow(3) as usize;
+
+        world.run_system_once(update_radius).unwrap();
+        let controller = world.resource::<Controller>();
+        assert_eq!(controller.load.len(), count);
+        assert_eq!(controller.build.len(), (2 * LOAD_RADIUS - 1).pow(3) as usize);
+        assert!(controller.load.iter().all(|pos| controller.state(*pos) == Some(ChunkState::Queued)));
+
+        // Move one chunk along X: far layer is unloaded, new layer is queued
+        world.entity_mut(camera).insert(Transform::from_xyz(RawChunk::SIZE_F32, 0.0, 0.0));
+        world.run_system_once(update_radius).unwrap();
+        let controller = world.resource::<Controller>();
+        let far = IVec3::new(-LOAD_RADIUS, 0, 0);
+        assert_eq!(controller.state(far), Some(ChunkState::Unloading));
+        assert!(controller.unload.contains(&far) && !controller.load.contains(&far) && !controller.build.contains_key(&far));
+        assert!(controller.load.contains(&IVec3::new(LOAD_RADIUS + 1, 0, 0)));
+        assert_eq!(controller.load.len(), count);
+    }
+
+    #[test]
+    fn unload_states() {
+        let mut world = World::new();
+        world.insert_resource(Controller::default());
+        world.spawn((MainCamera::new(), Transform::from_xyz(0.0, RawChunk::SIZE_F32 * 100.0, 0.0)));
+
+        let pos = IVec3::ZERO;
+        let mut controller = world.resource_mut::<Controller>();
+        controller.load.insert(pos);
+        controller.set_state(pos, Some(ChunkState::Queued));
+        controller.transitions.clear();
+
+        // Chunk is far from camera
+        world.run_system_once(update_radius).unwrap();
+        world.run_system_once(unload).unwrap();
+        let controller = world.resource::<Controller>();
+        let transitions: Vec<_> = controller.transitions.iter().filter(|t| t.chunk == pos).map(|t| (t.from, t.to)).collect();
+        assert_eq!(transitions, [
+            (Some(ChunkState::Queued), Some(ChunkState::Unloading)),
+            (Some(ChunkState::Unloading), None)
+        ]);
+        assert!(!controller.load.contains(&pos) && controller.state(pos).is_none());
+    }
+}
@@ -45,7 +45,6 @@ pub fn update(
     mut commands: Commands,
     assets: Res<AssetServer>,
     worlds: Res<Assets<WorldData>>,
-    mut materials: ResMut<Assets<ChunkMaterial>>,
     mut next_state: ResMut<NextState<MainState>>,
 
     mut world_name: ResMut<WorldName>,
@@ -81,12 +80,12 @@ pub fn update(
                         println!("Opening {};", data.name);
                         
                         let blocks = BlocksHandler::new(&assets, data.blocks.clone());
-                        let material = materials.add(ChunkMaterial::new(&blocks));
                         let res = WorldRes {
                             name: data.name.clone(),
                             handler: handle.clone(),
                             blocks,
-                            main_material: material,
+                            // Made in process when textures are loaded
+                            textures: Handle::default(),
                             entities: Vec::new()
                         };
 
@@ -124,28 +123,35 @@ pub fn reload(
 
 /// Load all assets if loaded - enter world
 pub fn process(
-    world: ResMut<WorldRes>,
+    mut world: ResMut<WorldRes>,
     assets: Res<AssetServer>,
+    models: Res<Assets<CustomModel>>,
     mut images: ResMut<Assets<Image>>,
     mut next_state: ResMut<NextState<MainState>>,
 ) {
+    // Wait for custom models with their textures, failed models are baked without data
+    let done = |m: &Handle<CustomModel>| assets.is_loaded_with_dependencies(m) || assets.recursive_dependency_load_state(m).is_failed();
+    if !world.blocks.models().iter().all(done) {
+        return;
+    }
+    let blocks = world.blocks.bake(&models);
+
     // todo loading check logic
-    let textures: Vec<_> = world.blocks.textures().iter().cloned().filter(|t| t.is_some())
-        .map(|t| t.unwrap()).collect();
-    
-    for texture in textures {
-        if assets.is_loaded(&texture) {
-            let image = images.get(&texture).unwrap();
-            if image.texture_descriptor.array_layer_count() != 1 { continue; }
-
-            // If image isn't proceeded yet - reinterpret
-            let image = images.get_mut(&texture).unwrap();
-            image.reinterpret_stacked_2d_as_array(6);
-        } else { 
-            // Wait for all assets loading
-            return;
-        }
+    let textures = blocks.textures();
+    // Faces without texture have default handle, failed textures are drawn with missing color
+    if !textures.iter().all(|t| t.path().is_none() || assets.is_loaded(t) || assets.load_state(t).is_failed()) {
+        // Wait for all assets loading
+        return;
+    }
+    for model in blocks.models().iter().filter(|m| assets.load_state(*m).is_failed()) {
+        println!("Model {} failed to load", model.path().map(|p| p.to_string()).unwrap_or_default());
+    }
+    for texture in textures.iter().filter(|t| assets.load_state(*t).is_failed()) {
+        println!("Texture {} failed to load", texture.path().map(|p| p.to_string()).unwrap_or_default());
     }
 
+    // Texture array with custom models textures
+    world.blocks = blocks;
+    world.update_textures(&mut images);
     next_state.set(MainState::InGame);
-}
\ No newline at end of file
+}
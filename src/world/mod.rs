@@ -16,11 +16,19 @@ pub struct WorldRes {
     pub handler: Handle<WorldData>,
     /// All world's blocks
     pub blocks: BlocksHandler,
-    pub main_material: Handle<ChunkMaterial>,
+    /// Blocks texture array, made by update_textures
+    pub textures: Handle<Image>,
     /// Remove this after exit world
     pub entities: Vec<Entity>
 }
 
+impl WorldRes {
+    /// Pack blocks textures into array for chunks rendering
+    pub fn update_textures(&mut self, images: &mut Assets<Image>) {
+        self.textures = images.add(rendering::texture_array(images, &self.blocks.textures()));
+    }
+}
+
 /// World data in ./worlds/
 #[derive(Clone, TypePath, Asset)]
 #[derive(Serialize, Deserialize)]
@@ -44,6 +52,10 @@ impl WorldData {
         let data = WorldData { name, ..default() };
         let file = dir.join("world.yaml");
         std::fs::write(&file, serde_yaml::to_string(&data).unwrap()).unwrap();
+
+        // Default schematic block mapping
+        let mapping = schematic::BlockMapping::default();
+        std::fs::write(dir.join(schematic::BlockMapping::FILE), serde_yaml::to_string(&mapping).unwrap()).unwrap();
     }
 
     /// Load all folders list